@@ -54,6 +54,7 @@ fi
 
 confirm 'Install config?' && install -vDm644 etc/config.toml /etc/2/config.toml
 confirm 'Install exclusions?' && install -vDm644 etc/exclusions.txt /etc/2/exclusions.txt
+confirm 'Install mirrors?' && install -vDm644 etc/mirrors.toml /etc/2/mirrors.toml
 confirm 'Install repo priority?' && install -vDm644 etc/repo_priority.txt /etc/2/repo_priority.txt
 
 confirm 'Install bash completions?' && install -vDm644 completions/bash /usr/share/bash-completion/completions/2
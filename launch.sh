@@ -3,7 +3,43 @@
 #
 # shellcheck disable=SC2154
 
-if command -v sudo >/dev/null 2>&1; then
+# returns 0 if all flags passed are read-only, in which case root isn't needed
+read_only() {
+    local arg
+    for arg in "$@"; do
+        case "${arg}" in
+            --list | --history | --about | --long-about | --stats | --rdeps | --upstream | --verify | \
+            --files | --only | --only=* | --tree | \
+            --list-repos | --list-sets | --list-new | --provides | --search | --regex | --verbose | --quiet | --json | --force | --yes | \
+            --version | --help)
+                ;;
+            --*)
+                return 1 ;;
+            -*)
+                [[ "${arg:1}" =~ ^[lHaAsRUkF/@NPQEvqjfyVh]+$ ]] || return 1 ;;
+        esac
+    done
+}
+
+# returns 0 if --pretend was passed, in which case nothing is modified
+pretending() {
+    local arg
+    for arg in "$@"; do
+        case "${arg}" in
+            --pretend)
+                return 0 ;;
+            --*)
+                ;;
+            -*)
+                [[ "${arg:1}" == *n* ]] && return 0 ;;
+        esac
+    done
+    return 1
+}
+
+if read_only "$@" || pretending "$@" || [[ "${EUID}" -eq 0 ]]; then
+    S=""
+elif command -v sudo >/dev/null 2>&1; then
     S="sudo"
 elif command -v doas >/dev/null 2>&1; then
     S="doas"
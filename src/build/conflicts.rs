@@ -0,0 +1,199 @@
+// src/build/conflicts.rs
+//! Detects conflicts between a package and installed packages
+//!
+//! A package conflicts with an installed package if it would install a file the installed
+//! package already owns, or if it declares a conflict with it in its LOCK. Directories are shared
+//! freely.
+
+use std::collections::BTreeMap;
+
+use anyhow::{
+    Result,
+    bail,
+};
+use tracing::{
+    instrument,
+    warn,
+};
+
+use crate::{
+    db::Db,
+    globals::{
+        config::{
+            CONFIG,
+            ConflictPolicy,
+        },
+        flags::Flags,
+    },
+    package::Package,
+    remove::manifest::{
+        Kind,
+        Record,
+    },
+    utils::comms::erm,
+};
+
+/// # Description
+/// The number of conflicting paths displayed per package
+const SHOWN: usize = 16;
+
+/// # Description
+/// A conflict with an installed package
+#[derive(Debug, PartialEq, Eq)]
+pub struct Conflict {
+    /// The installed package, as 'repo/name'
+    pub owner:    String,
+    /// Whether the conflict was declared in the LOCK
+    pub declared: bool,
+    /// The paths both packages would install
+    pub paths:    Vec<String>,
+}
+
+/// # Description
+/// Returns true if a declared conflict ('name' or 'repo/name') refers to a port
+fn refers_to(declared: &str, owner: &str) -> bool {
+    declared == owner
+        || owner
+            .rsplit_once('/')
+            .is_some_and(|(_, name)| name == declared)
+}
+
+/// # Description
+/// Finds the installed packages in ``db`` a package (``relpath``) conflicts with
+///
+/// Conflicts are sorted by owner
+#[instrument(skip(db, records))]
+fn find_in(db: &Db, relpath: &str, declared: &[String], records: &[Record]) -> Vec<Conflict> {
+    fn conflict<'a>(
+        conflicts: &'a mut BTreeMap<String, Conflict>,
+        owner: &str,
+    ) -> &'a mut Conflict {
+        conflicts
+            .entry(owner.to_string())
+            .or_insert_with(|| Conflict {
+                owner:    owner.to_string(),
+                declared: false,
+                paths:    Vec::new(),
+            })
+    }
+
+    let mut conflicts = BTreeMap::new();
+    for r in records.iter().filter(|r| r.kind != Kind::Dir) {
+        for owner in db.owners(&r.path).iter().filter(|o| *o != relpath) {
+            conflict(&mut conflicts, owner).paths.push(r.path.clone());
+        }
+    }
+
+    for (owner, _) in db.packages() {
+        if owner != relpath && declared.iter().any(|d| refers_to(d, owner)) {
+            conflict(&mut conflicts, owner).declared = true;
+        }
+    }
+
+    conflicts.into_values().collect()
+}
+
+/// # Description
+/// Finds the installed packages a package conflicts with
+///
+/// Owners are looked up in the database (see ``db``)
+pub fn find(package: &Package, records: &[Record]) -> Result<Vec<Conflict>> {
+    let db = Db::load()?;
+    Ok(find_in(&db, &package.relpath, &package.conflicts, records))
+}
+
+/// # Description
+/// Checks a package's staged records for conflicts with installed packages
+///
+/// Conflicts are displayed. Whether they're fatal depends on ``on_conflict`` in the config, though
+/// force downgrades refusal to a warning.
+///
+/// **Error conditions:**
+/// - the package conflicts with an installed package, and conflicts are refused
+pub fn check(package: &Package, records: &[Record]) -> Result<()> {
+    let conflicts = find(package, records)?;
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    for c in &conflicts {
+        warn!("'{package}' conflicts with '{}': {:?}", c.owner, c.paths);
+        if c.declared {
+            erm!("'{package}' declares a conflict with '{}'", c.owner);
+        }
+        if !c.paths.is_empty() {
+            erm!("'{package}' and '{}' both provide:", c.owner);
+        }
+        for p in c.paths.iter().take(SHOWN) {
+            erm!("  {p}");
+        }
+        if c.paths.len() > SHOWN {
+            erm!("  ...and {} more", c.paths.len() - SHOWN);
+        }
+    }
+
+    if CONFIG.general.on_conflict == ConflictPolicy::Refuse && !Flags::grab().force {
+        bail!(
+            "'{package}' conflicts with {} installed packages",
+            conflicts.len()
+        );
+    }
+
+    erm!("Installing '{package}' anyway");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Conflict,
+        find_in,
+    };
+    use crate::{
+        db::{
+            Db,
+            Installed,
+        },
+        remove::manifest::{
+            Kind,
+            Record,
+        },
+    };
+
+    #[test]
+    fn finds_overlapping_and_declared_conflicts() {
+        let mut db = Db::default();
+        let paths = |paths: &[&str]| Installed {
+            version: "1".to_string(),
+            paths:   paths
+                .iter()
+                .map(|p| (Kind::File, (*p).to_string()))
+                .collect(),
+        };
+        db.insert(
+            "main/vim",
+            paths(&["/usr/bin", "/usr/bin/vi", "/usr/bin/vim"]),
+        );
+        db.insert("main/nvi", paths(&["/usr/bin", "/usr/bin/nvi"]));
+        db.insert("main/tree", paths(&["/usr/bin", "/usr/bin/tree"]));
+
+        let mut records = ["/usr/bin", "/usr/bin/vi", "/usr/bin/ex"].map(Record::legacy);
+        records[0].kind = Kind::Dir;
+
+        let conflicts = find_in(&db, "main/busybox", &["nvi".to_string()], &records);
+        assert_eq!(conflicts, [
+            Conflict {
+                owner:    "main/nvi".to_string(),
+                declared: true,
+                paths:    Vec::new(),
+            },
+            Conflict {
+                owner:    "main/vim".to_string(),
+                declared: false,
+                paths:    vec!["/usr/bin/vi".to_string()],
+            },
+        ]);
+
+        assert!(find_in(&db, "main/vim", &[], &records).is_empty());
+    }
+}
@@ -0,0 +1,383 @@
+// src/build/dist.rs
+//! Creates and extracts dist tarballs
+
+use std::{
+    error::Error,
+    fmt::{
+        self,
+        Display,
+        Formatter,
+    },
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        self,
+        BufWriter,
+        Write,
+    },
+    path::{
+        Component,
+        Path,
+        PathBuf,
+    },
+    thread,
+};
+
+use glob::Pattern;
+use tar::{
+    Archive,
+    Builder,
+    EntryType,
+    Header,
+    HeaderMode,
+};
+use tracing::{
+    debug,
+    instrument,
+};
+use walkdir::WalkDir;
+
+use crate::shell::user;
+
+/// # Description
+/// Where exclusions are read from
+const EXCLUSIONS: &str = "/etc/2/exclusions.txt";
+
+/// # Description
+/// The zstd compression level used for dist tarballs
+const LEVEL: i32 = 19;
+
+/// # Description
+/// The ways creating or extracting a dist tarball can fail
+#[derive(Debug)]
+pub enum DistError {
+    /// A file couldn't be read
+    Read(PathBuf, io::Error),
+    /// A file couldn't be written
+    Write(PathBuf, io::Error),
+    /// The archive is corrupt or couldn't be (de)compressed
+    Archive(PathBuf, io::Error),
+    /// An exclusion isn't a valid glob
+    Exclusion(String, glob::PatternError),
+    /// An entry would be extracted outside the destination
+    UnsafePath(PathBuf),
+}
+
+impl Display for DistError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            | Self::Read(p, e) => write!(f, "Failed to read '{}': {e}", p.display()),
+            | Self::Write(p, e) => write!(f, "Failed to write '{}': {e}", p.display()),
+            | Self::Archive(p, e) => write!(f, "Corrupt dist tarball '{}': {e}", p.display()),
+            | Self::Exclusion(x, e) => write!(f, "Invalid exclusion '{x}': {e}"),
+            | Self::UnsafePath(p) => write!(f, "Refusing to extract unsafe path '{}'", p.display()),
+        }
+    }
+}
+
+impl Error for DistError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            | Self::Read(_, e) | Self::Write(_, e) | Self::Archive(_, e) => Some(e),
+            | Self::Exclusion(_, e) => Some(e),
+            | Self::UnsafePath(_) => None,
+        }
+    }
+}
+
+/// # Description
+/// Reads the exclusions from /etc/2/exclusions.txt
+///
+/// Each non-empty line that isn't a comment is a glob, relative to the prefix. A missing file
+/// means nothing is excluded.
+pub fn exclusions() -> Result<Vec<Pattern>, DistError> {
+    let path = Path::new(EXCLUSIONS);
+    let content = match fs::read_to_string(path) {
+        | Ok(c) => c,
+        | Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        | Err(e) => return Err(DistError::Read(path.to_path_buf(), e)),
+    };
+
+    parse_exclusions(&content)
+}
+
+/// # Description
+/// Parses exclusions, one glob per line
+fn parse_exclusions(content: &str) -> Result<Vec<Pattern>, DistError> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let l = l.trim_matches('/');
+            Pattern::new(l).map_err(|e| DistError::Exclusion(l.to_string(), e))
+        })
+        .collect()
+}
+
+/// # Description
+/// Returns true if a relative path, or any directory containing it, matches one of the globs
+pub fn matches_any(path: &Path, globs: &[Pattern]) -> bool {
+    path.ancestors()
+        .filter(|a| !a.as_os_str().is_empty())
+        .any(|a| globs.iter().any(|g| g.matches_path(a)))
+}
+
+/// # Description
+/// Formats a number of bytes like ``du -h``
+pub fn human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["", "K", "M", "G", "T"];
+
+    #[allow(clippy::cast_precision_loss)]
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 { format!("{bytes}") } else { format!("{size:.1}{}", UNITS[unit]) }
+}
+
+/// # Description
+/// Packages a destdir into a zstd-compressed dist tarball
+///
+/// Entries are stored under ``D/``, owned by root regardless of who owns the files on disk.
+/// Symlinks are stored as-is. The tarball is written next to ``dist`` and renamed into place once
+/// complete, so an interrupted packaging never leaves a partial tarball behind.
+///
+/// Returns a summary of the size before and after compression
+#[instrument]
+pub fn create(destdir: &Path, dist: &Path) -> Result<String, DistError> {
+    let tmp = dist.with_extension("zst.part");
+    let f = File::create(&tmp).map_err(|e| DistError::Write(tmp.clone(), e))?;
+
+    let archive_err = |e| DistError::Archive(tmp.clone(), e);
+    let mut encoder = zstd::Encoder::new(BufWriter::new(f), LEVEL).map_err(archive_err)?;
+    let threads = thread::available_parallelism().map_or(1, std::num::NonZero::get);
+    encoder
+        .multithread(u32::try_from(threads).unwrap_or(1))
+        .map_err(archive_err)?;
+
+    let mut builder = Builder::new(encoder);
+    builder.follow_symlinks(false);
+
+    let mut size = 0;
+    for entry in WalkDir::new(destdir).sort_by_file_name() {
+        let entry = entry.map_err(|e| {
+            DistError::Read(
+                e.path().unwrap_or(destdir).to_path_buf(),
+                e.into_io_error()
+                    .unwrap_or_else(|| io::Error::other("Filesystem loop")),
+            )
+        })?;
+        let path = entry.path();
+        let read_err = |e| DistError::Read(path.to_path_buf(), e);
+
+        let rel = path.strip_prefix(destdir).unwrap_or(path);
+        let name = Path::new("D").join(rel);
+        let meta = path.symlink_metadata().map_err(read_err)?;
+
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&meta, HeaderMode::Complete);
+        header.set_uid(0);
+        header.set_gid(0);
+
+        if meta.is_symlink() {
+            let target = fs::read_link(path).map_err(read_err)?;
+            builder
+                .append_link(&mut header, &name, target)
+                .map_err(archive_err)?;
+        } else if meta.is_file() {
+            size += meta.len();
+            let f = File::open(path).map_err(read_err)?;
+            builder
+                .append_data(&mut header, &name, f)
+                .map_err(archive_err)?;
+        } else {
+            builder
+                .append_data(&mut header, &name, io::empty())
+                .map_err(archive_err)?;
+        }
+    }
+
+    let encoder = builder.into_inner().map_err(archive_err)?;
+    let mut writer = encoder.finish().map_err(archive_err)?;
+    writer.flush().map_err(archive_err)?;
+    drop(writer);
+
+    let compressed = fs::metadata(&tmp).map_err(archive_err)?.len();
+    fs::rename(&tmp, dist).map_err(|e| DistError::Write(dist.to_path_buf(), e))?;
+
+    Ok(format!("[ {} ↘ ↘  {} ]", human(size), human(compressed)))
+}
+
+/// # Description
+/// Extracts a dist tarball into a destination
+///
+/// The leading ``D/`` is stripped, and entries matching an exclusion (see ``exclusions()``) are
+/// skipped. Permissions and modification times are preserved, as is ownership when running as
+/// root.
+///
+/// Returns the paths of the entries written, relative to the destination, in archive order
+#[instrument(skip(exclusions))]
+pub fn extract(
+    dist: &Path,
+    dest: &Path,
+    exclusions: &[Pattern],
+) -> Result<Vec<PathBuf>, DistError> {
+    let archive_err = |e| DistError::Archive(dist.to_path_buf(), e);
+
+    let f = File::open(dist).map_err(|e| DistError::Read(dist.to_path_buf(), e))?;
+    let decoder = zstd::Decoder::new(f).map_err(archive_err)?;
+
+    let mut archive = Archive::new(decoder);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_preserve_ownerships(user::is_root());
+    archive.set_overwrite(true);
+
+    let mut written = Vec::new();
+    for entry in archive.entries().map_err(archive_err)? {
+        let mut entry = entry.map_err(archive_err)?;
+        let path = entry.path().map_err(archive_err)?.into_owned();
+
+        // strip the leading D/
+        let rel = path.components().skip(1).collect::<PathBuf>();
+        if rel.as_os_str().is_empty() {
+            continue;
+        }
+
+        if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(DistError::UnsafePath(path));
+        }
+
+        if matches_any(&rel, exclusions) {
+            debug!("Excluded '{}'", rel.display());
+            continue;
+        }
+
+        let target = dest.join(&rel);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| DistError::Write(parent.to_path_buf(), e))?;
+        }
+
+        if entry.header().entry_type() == EntryType::Directory && target.is_dir() {
+            // leave existing directories alone
+            written.push(rel);
+            continue;
+        }
+
+        entry
+            .unpack(&target)
+            .map_err(|e| DistError::Write(target.clone(), e))?;
+        written.push(rel);
+    }
+
+    Ok(written)
+}
+
+/// # Description
+/// Lists the paths a dist tarball would extract, without extracting it
+///
+/// Paths are relative to the prefix, and entries matching an exclusion are skipped, as in
+/// ``extract()``
+#[instrument(skip(exclusions))]
+pub fn list(dist: &Path, exclusions: &[Pattern]) -> Result<Vec<PathBuf>, DistError> {
+    let archive_err = |e| DistError::Archive(dist.to_path_buf(), e);
+
+    let f = File::open(dist).map_err(|e| DistError::Read(dist.to_path_buf(), e))?;
+    let decoder = zstd::Decoder::new(f).map_err(archive_err)?;
+
+    let mut archive = Archive::new(decoder);
+    let mut listed = Vec::new();
+    for entry in archive.entries().map_err(archive_err)? {
+        let entry = entry.map_err(archive_err)?;
+        let path = entry.path().map_err(archive_err)?;
+
+        // strip the leading D/
+        let rel = path.components().skip(1).collect::<PathBuf>();
+        if rel.as_os_str().is_empty() || matches_any(&rel, exclusions) {
+            continue;
+        }
+        listed.push(rel);
+    }
+
+    Ok(listed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::symlink,
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+    use super::{
+        create,
+        extract,
+        list,
+        matches_any,
+        parse_exclusions,
+    };
+    use crate::utils::{
+        fail::Fail,
+        testing::Scratch,
+    };
+
+    #[test]
+    fn exclusions_cover_directory_contents() {
+        let exclusions =
+            parse_exclusions("# comment\nusr/share/doc\n/usr/share/locale/*/LC_MESSAGES\n")
+                .fail("Failed to parse exclusions");
+
+        assert!(matches_any(Path::new("usr/share/doc"), &exclusions));
+        assert!(matches_any(
+            Path::new("usr/share/doc/tree/README"),
+            &exclusions
+        ));
+        assert!(matches_any(
+            Path::new("usr/share/locale/de/LC_MESSAGES/tree.mo"),
+            &exclusions
+        ));
+        assert!(!matches_any(Path::new("usr/share/locale/de"), &exclusions));
+        assert!(!matches_any(Path::new("usr/share/docs"), &exclusions));
+        assert!(!matches_any(Path::new("usr/bin/tree"), &exclusions));
+    }
+
+    #[test]
+    fn extraction_lists_written_entries() {
+        let root = Scratch::new("dist");
+        let destdir = root.join("D");
+        fs::create_dir_all(destdir.join("usr/bin")).fail("Failed to create destdir");
+        fs::create_dir_all(destdir.join("usr/share/doc/tree")).fail("Failed to create destdir");
+        fs::write(destdir.join("usr/bin/tree"), "tree").fail("Failed to write file");
+        fs::write(destdir.join("usr/share/doc/tree/README"), "docs").fail("Failed to write file");
+        symlink("tree", destdir.join("usr/bin/t")).fail("Failed to create symlink");
+
+        let dist = root.join("tree=2.2.1.tar.zst");
+        create(&destdir, &dist).fail("Failed to create dist tarball");
+
+        let exclusions = parse_exclusions("usr/share/doc").fail("Failed to parse exclusions");
+        let prefix = root.join("prefix");
+        let written = extract(&dist, &prefix, &exclusions).fail("Failed to extract dist tarball");
+
+        let expected = ["usr", "usr/bin", "usr/bin/t", "usr/bin/tree", "usr/share"];
+        assert_eq!(written, expected.map(PathBuf::from));
+        assert_eq!(
+            list(&dist, &exclusions).fail("Failed to list dist tarball"),
+            written
+        );
+        assert_eq!(
+            fs::read_link(prefix.join("usr/bin/t")).ok(),
+            Some(PathBuf::from("tree"))
+        );
+        assert!(!prefix.join("usr/share/doc").exists());
+    }
+}
@@ -0,0 +1,372 @@
+// src/build/isolation.rs
+//! Isolates builds using Linux namespaces
+
+use std::{
+    env,
+    fs::{
+        self,
+        File,
+        OpenOptions as OO,
+    },
+    io::{
+        BufRead,
+        BufReader,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    path::Path,
+};
+
+use anyhow::Result;
+use tracing::warn;
+
+use crate::{
+    globals::config::CONFIG,
+    package::Package,
+    shell::user::{
+        self,
+        User,
+    },
+    utils::comms::erm,
+};
+
+/// # Description
+/// Where subordinate user ids are read from
+const SUBUID: &str = "/etc/subuid";
+
+/// # Description
+/// Where subordinate group ids are read from
+const SUBGID: &str = "/etc/subgid";
+
+/// # Description
+/// Messages commonly printed when a network access fails because there is no network
+const NETWORK_ERRORS: [&str; 9] = [
+    "Could not resolve host",
+    "Couldn't resolve host",
+    "Temporary failure in name resolution",
+    "Name or service not known",
+    "Network is unreachable",
+    "No address associated with hostname",
+    "failed to lookup address information",
+    "getaddrinfo",
+    "Failed to establish a new connection",
+];
+
+/// # Description
+/// Returns true if the build for a package should be isolated
+///
+/// Isolation is enabled in the config, and ports may opt out with ``ISOLATE=0`` in BUILD, which is
+/// recorded as ``isolate = false`` in LOCK
+pub fn enabled(package: &Package) -> bool {
+    CONFIG.general.isolate_builds && package.isolate.unwrap_or(true)
+}
+
+/// # Description
+/// Returns true if the build for a package should be run without network access
+///
+/// Sources are downloaded before building, so builds don't get network access unless the port opts
+/// in with ``NETWORK=1`` in BUILD, which is recorded as ``network = true`` in LOCK
+pub const fn offline(package: &Package) -> bool { !package.network }
+
+/// # Description
+/// Finds a user's first range of subordinate ids in the contents of /etc/subuid or /etc/subgid
+///
+/// Entries are 'user:start:count', where user is a name or a uid
+///
+/// Returns the start and count
+fn parse_subordinate(contents: &str, user: &User) -> Option<(u32, u32)> {
+    contents.lines().find_map(|line| {
+        let mut fields = line.trim().split(':');
+        let owner = fields.next()?;
+        if owner != user.name && owner != user.uid.to_string() {
+            return None;
+        }
+        Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+    })
+}
+
+/// # Description
+/// Returns true if a program is in ``$PATH``
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|d| d.join(program).is_file()))
+}
+
+/// # Description
+/// Returns the unshare flags mapping the unprivileged build user's subordinate ids into its user
+/// namespace, after root
+///
+/// Without them, only root exists in the namespace, so changing ownership to any other id fails.
+/// Mapping them requires an entry for the user in both /etc/subuid and /etc/subgid, as well as
+/// ``newuidmap`` and ``newgidmap`` from shadow.
+fn subordinate_flags() -> Option<[String; 2]> {
+    let user = user::build_user()
+        .cloned()
+        .or_else(|| user::current().ok())?;
+    if !in_path("newuidmap") || !in_path("newgidmap") {
+        return None;
+    }
+
+    let range = |file| parse_subordinate(&fs::read_to_string(file).ok()?, &user);
+    let (uid, uids) = range(SUBUID)?;
+    let (gid, gids) = range(SUBGID)?;
+
+    // util-linux takes the outer id, then the inner id
+    Some([
+        format!("--map-users={uid},1,{uids}"),
+        format!("--map-groups={gid},1,{gids}"),
+    ])
+}
+
+/// # Description
+/// Returns the unshare flags for a build's user namespace
+///
+/// Builds run as root don't need one, as root may create the other namespaces itself, and keeps
+/// every id. Unprivileged builds are mapped to root, along with their subordinate ids if they have
+/// any (see ``subordinate_flags()``).
+fn user_flags() -> Vec<String> {
+    if !user::builds_unprivileged() {
+        return Vec::new();
+    }
+
+    let mut flags = vec!["--user".to_string(), "--map-root-user".to_string()];
+    match subordinate_flags() {
+        | Some(subordinate) => flags.extend(subordinate),
+        | None => warn!(
+            "No subordinate ids are available to the build user, so builds can't change ownership \
+             to anyone but root"
+        ),
+    }
+    flags
+}
+
+/// # Description
+/// Returns true if a build's user namespace only contains root, such that changing ownership to any
+/// other id fails
+fn only_maps_root(package: &Package) -> bool {
+    (enabled(package) || offline(package))
+        && user::builds_unprivileged()
+        && subordinate_flags().is_none()
+}
+
+/// # Description
+/// Quotes a string for bash
+fn quote(string: &str) -> String { format!("'{}'", string.replace('\'', r"'\''")) }
+
+/// # Description
+/// Wraps a command such that it runs in fresh namespaces
+///
+/// If the build is isolated (see ``enabled()``), the command runs in fresh mount and pid namespaces,
+/// as well as a user namespace if the build is unprivileged (see ``user_flags()``). Every mount is
+/// made read-only, except ``$BLD`` (and by extension ``$D``) and the pseudo filesystems. ``$SRC``
+/// is explicitly made read-only. ``/tmp`` and ``/run`` are replaced with private tmpfs mounts, so
+/// builds can't write to the host's.
+///
+/// If the build is offline (see ``offline()``), the command runs in a fresh network namespace. When
+/// building unprivileged, creating one requires a user namespace as well.
+///
+/// Since the environment doesn't carry over functions or shell options, ``/usr/share/2/envs/core``
+/// and ``$PORT/BUILD`` are sourced again, and the outer shell's ``-e`` and ``-x`` are passed
+/// through. Meant to be used within ``pkgexec!()``, which exports the variables the wrapper relies
+/// on and sets those options.
+pub fn wrap(command: &str, package: &Package) -> String {
+    let isolate = enabled(package);
+    let offline = offline(package);
+
+    if !isolate && !offline {
+        return command.to_string();
+    }
+
+    let mut flags = user_flags();
+    if isolate {
+        flags.extend(["--mount", "--pid", "--fork", "--mount-proc"].map(String::from));
+    }
+    if offline {
+        flags.push("--net".to_string());
+    }
+
+    let setup = if isolate {
+        r#"
+    mount --make-rprivate /
+    mount --bind "$BLD" "$BLD"
+
+    findmnt -rno TARGET | while read -r m; do
+        m=$(printf '%b' "$m")
+        case "$m" in
+            "$BLD" | /proc | /proc/* | /sys | /sys/* | /dev | /dev/* | /run | /run/* | /tmp) continue ;;
+        esac
+
+        if ! mount -o remount,bind,ro "$m" 2>/dev/null; then
+            if [ "$m" = / ]; then
+                echo "Failed to make / read-only" >&2
+                exit 1
+            fi
+            echo "Failed to make '$m' read-only" >&2
+        fi
+    done
+
+    mount --bind "$SRC" "$SRC"
+    mount -o remount,bind,ro "$SRC"
+
+    mount -t tmpfs -o mode=1777 tmpfs /tmp
+    mount -t tmpfs -o mode=0755 tmpfs /run
+    "#
+    } else {
+        ""
+    };
+
+    let inner = format!(
+        r#"
+    set -e
+    {setup}
+    set -"${{1//[^ex]}}"
+
+    source /usr/share/2/envs/core
+    source "$PORT/BUILD"
+
+    {command}
+    "#
+    );
+
+    // the outer shell's options are passed as $1
+    format!(
+        r#"unshare {} -- bash -c {} bash "$-""#,
+        flags.join(" "),
+        quote(&inner)
+    )
+}
+
+/// # Description
+/// Returns the first line that looks like a failed network access
+fn find_network_error<I>(lines: I) -> Option<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    lines
+        .into_iter()
+        .find(|l| NETWORK_ERRORS.iter().any(|e| l.contains(e)))
+}
+
+/// # Description
+/// Returns the first line that looks like a failure to change ownership to an unmapped id
+fn find_ownership_error<I>(lines: I) -> Option<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    lines
+        .into_iter()
+        .find(|l| l.contains("ownership") && l.contains("Invalid argument"))
+}
+
+/// # Description
+/// Checks whether a failed build failed because of its isolation, and reports it if so
+///
+/// Offline builds are checked for attempts to access the network, and builds whose user namespace
+/// only maps root (see ``only_maps_root()``) for attempts to change ownership. Only the part of the
+/// build log written after ``offset`` is checked. Reports are sent to the user and appended to the
+/// build log.
+pub fn report_failure(package: &Package, log: &Path, offset: u64) -> Result<()> {
+    let mut f = File::open(log)?;
+    f.seek(SeekFrom::Start(offset))?;
+    let lines = BufReader::new(f)
+        .lines()
+        .map_while(Result::ok)
+        .collect::<Vec<_>>();
+
+    if offline(package)
+        && let Some(line) = find_network_error(lines.iter().cloned())
+    {
+        let report = format!(
+            "Build for '{package}' appears to have failed by attempting to access the network, \
+             which is disabled for builds. If the port needs network access, set 'NETWORK=1' in \
+             its BUILD and regenerate its LOCK."
+        );
+        report_line(log, &report, &line)?;
+    }
+
+    if only_maps_root(package)
+        && let Some(line) = find_ownership_error(lines)
+    {
+        let report = format!(
+            "Build for '{package}' appears to have failed by changing ownership to a user or group \
+             other than root, which only exists in isolated builds if the build user has \
+             subordinate ids in /etc/subuid and /etc/subgid, and newuidmap and newgidmap are \
+             installed."
+        );
+        report_line(log, &report, &line)?;
+    }
+
+    Ok(())
+}
+
+/// # Description
+/// Reports why a build failed, along with the offending line, to the user and the build log
+fn report_line(log: &Path, report: &str, line: &str) -> Result<()> {
+    warn!("{report} Offending line: {line}");
+    erm!("{report}");
+    erm!("Offending line: {}", line.trim());
+
+    let mut f = OO::new().append(true).open(log)?;
+    writeln!(f, "\n[2] {report}\n[2] Offending line: {}", line.trim())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{
+        find_network_error,
+        find_ownership_error,
+        parse_subordinate,
+    };
+    use crate::shell::user::User;
+
+    #[test]
+    fn detects_dns_failures() {
+        let lines = [
+            "    Updating crates.io index",
+            "warning: spurious network error (3 tries remaining): [6] Couldn't resolve host name \
+             (Could not resolve host: index.crates.io)",
+            "error: failed to get `anyhow` as a dependency of package `two v0.1.0`",
+        ]
+        .map(ToString::to_string);
+
+        assert!(find_network_error(lines).is_some_and(|l| l.contains("spurious network error")));
+    }
+
+    #[test]
+    fn ignores_ordinary_failures() {
+        let lines = ["make: *** [Makefile:420: all] Error 2"].map(ToString::to_string);
+        assert!(find_network_error(lines).is_none());
+    }
+
+    #[test]
+    fn detects_ownership_failures() {
+        let lines = [
+            "install -Dm755 foo /build/D/usr/bin/foo",
+            "install: cannot change ownership of '/build/D/var/lib/foo': Invalid argument",
+        ]
+        .map(ToString::to_string);
+        assert!(find_ownership_error(lines).is_some_and(|l| l.starts_with("install: cannot")));
+    }
+
+    #[test]
+    fn finds_subordinate_ids_by_name_or_uid() {
+        let user = User {
+            name: "builder".to_string(),
+            uid:  1001,
+            gid:  1001,
+            home: PathBuf::from("/home/builder"),
+        };
+
+        let contents = "alice:100000:65536\nbuilder:165536:65536\nbuilder:300000:10\n";
+        assert_eq!(parse_subordinate(contents, &user), Some((165_536, 65_536)));
+        assert_eq!(
+            parse_subordinate("1001:231072:65536\n", &user),
+            Some((231_072, 65_536))
+        );
+        assert_eq!(parse_subordinate("alice:100000:65536\n", &user), None);
+    }
+}
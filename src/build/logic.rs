@@ -1,12 +1,17 @@
 // src/build/logic.rs
 //! Defines the logic for package builds
 
+use std::path::PathBuf;
+
 use tracing::{
     debug,
     info,
 };
 
-use super::script;
+use super::{
+    script,
+    transaction,
+};
 use crate::{
     globals::{
         config::CONFIG,
@@ -14,16 +19,19 @@ use crate::{
     },
     package::{
         Package,
+        dependencies::Stage,
         stats::{
             self,
             PackageStats,
         },
     },
-    remove::logic::{
-        clean,
-        remove_dead_files_after_update,
+    remove::{
+        logic::{
+            clean,
+            dead_files,
+        },
+        manifest::Record,
     },
-    shell::cmd::exec,
     utils::{
         comms::{
             msg,
@@ -57,38 +65,69 @@ pub enum BuildStatus {
 /// dist installs.
 ///
 /// Returns false if the package has already been installed
-pub fn install(package: &Package) -> InstallStatus {
-    if !package.data.installed_version.is_empty() && !Flags::grab().force {
+///
+/// If ``r#override`` is true, the package is reinstalled even if it's already installed
+pub fn install(package: &Package, r#override: bool) -> InstallStatus {
+    let status = install_status(package, r#override);
+    if matches!(status, InstallStatus::Dist) {
+        dist_install(package, &[]);
+    }
+    status
+}
+
+/// # Description
+/// Decides how a package would be installed, without installing it
+///
+/// See ``install()``
+pub fn install_status(package: &Package, r#override: bool) -> InstallStatus {
+    if !package.data.installed_version.is_empty() && !Flags::grab().force && !r#override {
         if package.version == package.data.installed_version {
             InstallStatus::Already
         } else {
             InstallStatus::UpdateInstead
         }
     } else if package.dist_exists() {
-        dist_install(package);
         InstallStatus::Dist
     } else {
         InstallStatus::BuildFirst
     }
 }
 
+/// # Description
+/// Returns true if a package is built and doesn't need rebuilding
+///
+/// See ``build()``
+pub fn is_built(package: &Package, r#override: bool) -> bool {
+    package.dist_exists() && !Flags::grab().force && !r#override
+}
+
 /// # Description
 /// Builds a package, calling functions in ``super::script``
 ///
 /// Returns false if the package has already been built
-pub fn build(package: &Package, r#override: bool) -> (BuildStatus, Option<PackageStats>) {
+///
+/// If ``r#override`` is true, the package is rebuilt even if it's already built. The stage is passed
+/// through to ``script::build()``.
+pub fn build(
+    package: &Package,
+    r#override: bool,
+    stage: Stage,
+) -> (BuildStatus, Option<PackageStats>) {
     let stats = stats::load(package).fail("Failed to load package stats");
 
-    let built = package.dist_exists() && !Flags::grab().force && !r#override;
-
-    if built {
+    if is_built(package, r#override) {
         debug!("Package '{package}' is already built");
         (BuildStatus::Already, None)
     } else {
-        msg!("󰏗  Building '{package}'...");
-        info!("Building '{package}'...");
+        if stage == Stage::Bootstrap {
+            msg!("󰏗  Bootstrapping '{package}'...");
+            info!("Bootstrapping '{package}'...");
+        } else {
+            msg!("󰏗  Building '{package}'...");
+            info!("Building '{package}'...");
+        }
         script::prep(package);
-        script::build(package);
+        script::build(package, stage);
 
         if CONFIG.general.clean_after_build {
             clean(package);
@@ -100,41 +139,15 @@ pub fn build(package: &Package, r#override: bool) -> (BuildStatus, Option<Packag
 /// # Description
 /// Installs a package from its dist tarball. Also evaluates the post-install instructions.
 ///
-/// Uses tar under the hood. Reads /etc/2/exclusions.txt. Logs the installed files to a manifest.
-fn dist_install(package: &Package) {
-    let command = format!(
-        r#"
-
-    PREFIX={}
-    mkdir -pv "$PREFIX"
-
-    tar xvf {:?} -C "$PREFIX"       \
-        --strip-components=1        \
-        --keep-directory-symlink    \
-        --numeric-owner             \
-        --no-overwrite-dir          \
-        --exclude-from='/etc/2/exclusions.txt' |
-    sed -e 's@/$@@' \
-        -e 's@^D@@' \
-        -e '/^$/d'  |
-    tee {:?}/.data/MANIFEST={}
-    echo "{}" > {:?}/.data/INSTALLED
-
-    ldconfig
-
-    "#,
-        CONFIG.general.prefix,
-        package.data.dist,
-        package.data.port_dir,
-        package.version,
-        package.version,
-        package.data.port_dir,
-    );
-
+/// The install is transactional (see ``super::transaction``), so a failed install leaves the
+/// system as it was. Dead paths are removed as part of the transaction. Reads
+/// /etc/2/exclusions.txt. Logs the installed files to a manifest.
+///
+/// Returns the records of the dead paths that were removed
+fn dist_install(package: &Package, dead: &[(Record, PathBuf)]) -> Vec<Record> {
     msg!("󱧘  Installing '{package}'...");
     info!("Installing '{package}'...");
-    exec(&command, None).fail("Failed to perform dist install");
-    script::post(package);
+    transaction::install(package, dead).efail(|| format!("Failed to install '{package}'"))
 }
 
 /// # Description
@@ -147,20 +160,14 @@ fn dist_install(package: &Package) {
 /// If the dist tarball for the new version exists, uses that. Otherwise, builds the package and
 /// then dist installs it.
 ///
-/// After dist installing, if the new version isn't the old version, removes any dead files by
-/// calling ``remove_dead_files_after_update()``. Finally returns true.
+/// If the new version isn't the old version, any dead files (see ``remove::logic::dead_files()``)
+/// are removed as part of the dist install. Finally returns true.
 ///
-/// Uses tar under the hood. Reads /etc/2/exclusions.txt. Logs the installed files to a manifest.
+/// Installs transactionally. Reads /etc/2/exclusions.txt. Logs the installed files to a manifest.
 pub fn update(package: &Package) -> UpdateStatus {
-    let flags = Flags::grab();
-    let force = flags.force;
-    let quiet = flags.quiet;
-    if !package.data.is_installed && !force {
-        return UpdateStatus::NotInstalled;
-    }
-
-    if !package.is_outdated() && !force {
-        return UpdateStatus::Latest;
+    let status = update_status(package);
+    if matches!(status, UpdateStatus::NotInstalled | UpdateStatus::Latest) {
+        return status;
     }
 
     msg!(
@@ -170,21 +177,44 @@ pub fn update(package: &Package) -> UpdateStatus {
         package.version
     );
 
-    if !package.dist_exists() {
-        return UpdateStatus::BuildFirst;
+    if matches!(status, UpdateStatus::BuildFirst) {
+        return status;
     }
 
-    dist_install(package);
-    if package.version != package.data.installed_version {
-        if !quiet {
-            pr!(
-                "Removing dead files for '{}={}'",
-                package.name,
-                package.data.installed_version
-            );
+    let dead = if package.version == package.data.installed_version {
+        Vec::new()
+    } else {
+        dead_files(package).unwrap_or_default()
+    };
+
+    let removed = dist_install(package, &dead);
+    if !removed.is_empty() && !Flags::grab().quiet {
+        pr!(
+            "Removed dead files for '{}={}'",
+            package.name,
+            package.data.installed_version
+        );
+        for r in &removed {
+            pr!("'{}' -x", r.path.trim_start_matches('/'));
         }
-        remove_dead_files_after_update(package);
     }
 
     UpdateStatus::Dist
 }
+
+/// # Description
+/// Decides how a package would be updated, without updating it
+///
+/// See ``update()``
+pub fn update_status(package: &Package) -> UpdateStatus {
+    let force = Flags::grab().force;
+    if !package.data.is_installed && !force {
+        UpdateStatus::NotInstalled
+    } else if !package.is_outdated() && !force {
+        UpdateStatus::Latest
+    } else if !package.dist_exists() {
+        UpdateStatus::BuildFirst
+    } else {
+        UpdateStatus::Dist
+    }
+}
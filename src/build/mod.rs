@@ -1,6 +1,10 @@
 // src/build/mod.rs
 //! Contains modules related to building packages
 
+pub mod conflicts;
+pub mod dist;
+pub mod isolation;
 pub mod logic;
 pub mod qa;
 pub mod script;
+pub mod transaction;
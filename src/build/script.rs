@@ -1,16 +1,36 @@
 // src/build/script.rs
 //! Interfaces with $PORT/BUILD
 
-use std::path::Path;
+use std::{
+    fs,
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use tracing::warn;
 
-use super::qa;
+use super::{
+    dist,
+    isolation,
+    qa,
+};
 use crate::{
     fetch::download::normalize_tarball,
     globals::config::CONFIG,
-    package::Package,
+    package::{
+        Package,
+        dependencies::Stage,
+    },
     remove::logic::clean,
-    shell::cmd::pkgexec,
+    shell::{
+        cmd::pkgexec,
+        user,
+    },
     utils::{
+        comms::pr,
         fail::{
             BoolFail,
             Fail,
@@ -57,7 +77,8 @@ fn check_hashes(package: &Package, no_source: bool) {
 /// ### Description
 /// Sets up for a build
 ///
-/// The setup process involves checking hashes, cleaning, and extracting the sources to the build directory
+/// The setup process involves checking hashes, cleaning, handing the build and dist directories
+/// over to the build user, and extracting the sources to the build directory
 fn setup(package: &Package) {
     let no_source = package.source.url.is_empty();
     if CONFIG.general.check_hashes {
@@ -65,12 +86,20 @@ fn setup(package: &Package) {
     }
     clean(package);
 
+    if let Some(user) = user::build_user() {
+        for dir in [".build", ".dist"] {
+            user::chown(&package.data.port_dir.join(dir), user).efail(|| {
+                format!(
+                    "Failed to hand '{dir}' for '{package}' over to '{}'",
+                    user.name
+                )
+            });
+        }
+    }
+
     let command = format!(
         r#"
 
-    XTR="/tmp/2/extraction"
-    rm -rf "$XTR"
-
     if {no_source}; then
         echo "Package has no tarball; skipping extraction" >&2
         exit 0
@@ -81,17 +110,19 @@ fn setup(package: &Package) {
         exit 0
     fi
 
-    mkdir -pv "$XTR"
+    XTR=$(mktemp -d)
+    trap 'rm -rf "$XTR"' EXIT
 
     # example: /var/ports/testing/tree/.sources/tree=2.2.1.tar.bz2
-    tar xf "$SRC/{package}.tar."*z* -C $XTR
+    tar xf "$SRC/{package}.tar."*z* -C "$XTR"
     shopt -s dotglob
-    mv -f $XTR/*/* "$BLD"/
+    mv -f "$XTR"/*/* "$BLD"/
 
     "#
     );
 
-    pkgexec!(&command, package).efail(|| format!("Build for '{package}' died in setup"));
+    pkgexec!(&command, package, user::build_user())
+        .efail(|| format!("Build for '{package}' died in setup"));
 }
 
 /// ### Description
@@ -100,41 +131,55 @@ fn setup(package: &Package) {
 /// Defined in BUILD under ``2b()``
 ///
 /// Build instructions should DESTDIR install to "$BLD/D"
-pub fn build(package: &Package) {
+///
+/// In the bootstrap stage, ``BOOTSTRAP=1`` is exported so BUILD can perform a reduced build
+///
+/// Build instructions are isolated and run without network access, unless the port opts out (see
+/// ``isolation::wrap()``). Setup and build instructions run as the build user, if one is
+/// configured.
+///
+/// The dist tarball is packaged with all files owned by root, regardless of who built them (see
+/// ``dist::create()``).
+pub fn build(package: &Package, stage: Stage) {
     setup(package);
 
     qa::envs_properly_initialized(package)
         .or_efail(|| format!("QA: Detected uninitialized or unused environment for '{package}'"));
 
-    let command = r#"cd "$BLD"; 2b"#;
-    pkgexec!(command, package).efail(|| format!("Build for '{package}' died"));
+    let command = if stage == Stage::Bootstrap {
+        r#"export BOOTSTRAP=1; cd "$BLD"; 2b"#
+    } else {
+        r#"cd "$BLD"; 2b"#
+    };
+    let command = isolation::wrap(command, package);
+
+    let log = package.data.port_dir.join(".logs/build.log");
+    let offset = fs::metadata(&log).map_or(0, |m| m.len());
+
+    let result = pkgexec!(&command, package, user::build_user());
+    if result.is_err()
+        && let Err(e) = isolation::report_failure(package, &log, offset)
+    {
+        warn!("Failed to check the build log for isolation failures: {e}");
+    }
+    result.efail(|| format!("Build for '{package}' died"));
 
     qa::destdir_has_stuff(package)
         .or_efail(|| format!("QA: Detected empty destdir for '{package}'"));
     qa::libs_ok(package)
         .or_efail(|| format!("QA: Detected wrong-architecture libraries for '{package}'"));
 
-    let command = format!(
-        r#"
-    cd "$BLD"
-    ORIG=$(du -bsh D | awk '{{print $1}}')
-    TB="$PORT/.dist/{package}.tar.zst"
-
-    echo -e "Packaging..."
-    tar cf - D | zstd --rm -f -T0 -19 -o "$TB" >/dev/null 2>&1
-
-    FINL=$(du -bsh "$TB" | awk '{{print $1}}')
-    echo -e "\x1b[0;37;1m[ $ORIG ↘ ↘  $FINL ]\x1b[0m" >&2
-    "#
-    );
-
-    pkgexec!(&command, package).efail(|| format!("Packaging for '{package}' died"));
+    pr!("Packaging...");
+    let destdir = package.data.port_dir.join(".build/D");
+    let summary = dist::create(&destdir, &package.data.dist)
+        .efail(|| format!("Packaging for '{package}' died"));
+    pr!("\x1b[0;37;1m{summary}");
 }
 
 /// ### Description
 /// Evaluates pre-install instructions
 ///
-/// These instructions are defined in BUILD under the function ``2a()``.
+/// These instructions are defined in BUILD under the function ``2a()``. They run as root.
 pub fn prep(package: &Package) {
     let command = r"
 
@@ -156,7 +201,9 @@ pub fn prep(package: &Package) {
 ///
 /// The instructions should not interact with the build, but rather should perform any necessary
 /// post-install actions
-pub fn post(package: &Package) {
+///
+/// Returns an error rather than failing, so the install can be rolled back
+pub fn post(package: &Package) -> Result<()> {
     let command = r"
 
     if command -V 2z 2>&1 | grep 'is a function' >/dev/null 2>&1; then
@@ -167,5 +214,6 @@ pub fn post(package: &Package) {
     "
     .to_string();
 
-    pkgexec!(&command, package).efail(|| format!("Build for '{package}' died in post-install"));
+    pkgexec!(&command, package)
+        .with_context(|| format!("Build for '{package}' died in post-install"))
 }
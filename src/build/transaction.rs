@@ -0,0 +1,964 @@
+// src/build/transaction.rs
+//! Installs dist tarballs transactionally
+//!
+//! Dist tarballs are extracted to a staging directory, verified, and then committed to the prefix
+//! file by file. Every change is recorded in a journal before it's made, so a failed or interrupted
+//! transaction can be rolled back, either right away or on the next run.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fmt::{
+        self,
+        Display,
+        Formatter,
+    },
+    fs::{
+        self,
+        File,
+        OpenOptions as OO,
+    },
+    io::{
+        self,
+        ErrorKind as IOE,
+        Write,
+    },
+    os::unix::fs::{
+        MetadataExt,
+        PermissionsExt,
+        lchown,
+        symlink,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
+use glob::Pattern;
+use tracing::{
+    debug,
+    info,
+    instrument,
+    warn,
+};
+use walkdir::WalkDir;
+
+use super::{
+    conflicts,
+    dist,
+    script,
+};
+use crate::{
+    db,
+    globals::config::CONFIG,
+    package::{
+        Package,
+        configs,
+    },
+    remove::manifest::{
+        self,
+        Record,
+    },
+    shell::cmd::exec,
+    utils::comms::{
+        erm,
+        msg,
+        pr,
+    },
+};
+
+/// # Description
+/// Where transactions are staged and journaled
+const TRANSACTION_DIR: &str = "/var/lib/2/transaction";
+
+/// # Description
+/// An entry in the transaction journal
+///
+/// Paths are absolute, so a journal can be replayed without knowing the prefix
+#[derive(Debug, PartialEq, Eq)]
+enum Entry {
+    /// The transaction began for a package ('repo/name') at a version
+    Begin { relpath: String, version: String },
+    /// The dist tarball was extracted to the staging directory
+    Staged,
+    /// A directory was created
+    Dir(PathBuf),
+    /// A file was installed where nothing existed
+    New(PathBuf),
+    /// A file was installed over an existing one, which was backed up
+    Replace(PathBuf),
+    /// A dead path from the installed version was removed
+    ///
+    /// Files are backed up by renaming them. Directories are only removed once the transaction is
+    /// committed, if they're empty.
+    Remove(PathBuf),
+    /// All files were committed and dead paths removed
+    Commit,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            | Self::Begin { relpath, version } => write!(f, "BEGIN\t{relpath}\t{version}"),
+            | Self::Staged => write!(f, "STAGED"),
+            | Self::Dir(p) => write!(f, "DIR\t{}", p.display()),
+            | Self::New(p) => write!(f, "NEW\t{}", p.display()),
+            | Self::Replace(p) => write!(f, "REPLACE\t{}", p.display()),
+            | Self::Remove(p) => write!(f, "REMOVE\t{}", p.display()),
+            | Self::Commit => write!(f, "COMMIT"),
+        }
+    }
+}
+
+impl FromStr for Entry {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let (kind, rest) = line.split_once('\t').unwrap_or((line, ""));
+        Ok(match kind {
+            | "BEGIN" => {
+                let (relpath, version) = rest.split_once('\t').context("Malformed BEGIN")?;
+                Self::Begin {
+                    relpath: relpath.to_string(),
+                    version: version.to_string(),
+                }
+            },
+            | "STAGED" => Self::Staged,
+            | "DIR" => Self::Dir(rest.into()),
+            | "NEW" => Self::New(rest.into()),
+            | "REPLACE" => Self::Replace(rest.into()),
+            | "REMOVE" => Self::Remove(rest.into()),
+            | "COMMIT" => Self::Commit,
+            | _ => bail!("Unknown journal entry '{line}'"),
+        })
+    }
+}
+
+/// # Description
+/// A write-ahead journal
+///
+/// Entries are synced to disk before the change they describe is made
+struct Journal(File);
+
+impl Journal {
+    fn create(path: &Path) -> Result<Self> {
+        let f = OO::new()
+            .create_new(true)
+            .append(true)
+            .open(path)
+            .context("Failed to create the journal")?;
+        Ok(Self(f))
+    }
+
+    fn record(&mut self, entry: &Entry) -> Result<()> {
+        writeln!(self.0, "{entry}").context("Failed to write to the journal")?;
+        self.0.sync_data().context("Failed to sync the journal")
+    }
+
+    fn read(path: &Path) -> Result<Vec<Entry>> {
+        let entries = fs::read_to_string(path)
+            .context("Failed to read the journal")?
+            .lines()
+            // a torn final line is ignored, as the change it describes was never made
+            .map_while(|l| l.parse().ok())
+            .collect();
+        Ok(entries)
+    }
+}
+
+/// # Description
+/// An in-progress transaction
+struct Transaction {
+    dir:       PathBuf,
+    prefix:    PathBuf,
+    journal:   Journal,
+    /// Globs for protected paths (see ``package::configs``)
+    protected: Vec<Pattern>,
+    /// The records of the previously installed version, by path
+    installed: HashMap<String, Record>,
+    /// Protected files that were kept, with their new versions installed as ``.new``
+    preserved: Vec<PathBuf>,
+    /// Dead paths from the installed version that were removed
+    removed:   Vec<Record>,
+}
+
+impl Transaction {
+    /// # Description
+    /// Begins a transaction in ``dir`` for a package
+    ///
+    /// **Error conditions:**
+    /// - another transaction is pending
+    fn begin(dir: &Path, prefix: &Path, package: &Package) -> Result<Self> {
+        if dir.join("JOURNAL").exists() {
+            bail!("Another transaction is pending in '{}'", dir.display());
+        }
+
+        if dir.exists() {
+            fs::remove_dir_all(dir).context("Failed to clear a stale transaction")?;
+        }
+        fs::create_dir_all(dir.join("staging"))
+            .context("Failed to create the staging directory")?;
+        fs::create_dir_all(prefix).context("Failed to create the prefix")?;
+
+        let mut journal = Journal::create(&dir.join("JOURNAL"))?;
+        journal.record(&Entry::Begin {
+            relpath: package.relpath.clone(),
+            version: package.version.clone(),
+        })?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            prefix: prefix.to_path_buf(),
+            journal,
+            protected: Vec::new(),
+            installed: HashMap::new(),
+            preserved: Vec::new(),
+            removed: Vec::new(),
+        })
+    }
+
+    /// # Description
+    /// Protects paths matching ``patterns`` against being overwritten if they were modified since
+    /// they were installed, according to the previously installed version's records
+    fn protect(&mut self, patterns: Vec<Pattern>, installed: Vec<Record>) {
+        self.protected = patterns;
+        self.installed = installed.into_iter().map(|r| (r.path.clone(), r)).collect();
+    }
+
+    fn staging(&self) -> PathBuf { self.dir.join("staging") }
+
+    /// # Description
+    /// Extracts a dist tarball to the staging directory
+    ///
+    /// Exclusions are applied (see ``dist::exclusions()``). The manifest is written alongside the
+    /// staging directory from the entries extracted, since committing moves files out of it. It
+    /// records the type, mode, owner, size, and hash of each path (see ``remove::manifest``).
+    ///
+    /// Returns the records
+    fn stage(&mut self, dist: &Path) -> Result<Vec<Record>> {
+        let staging = self.staging();
+        let written = dist::extract(dist, &staging, &dist::exclusions()?)?;
+
+        let records = written
+            .iter()
+            .map(|p| Record::new(&format!("/{}", p.display()), &staging.join(p)))
+            .collect::<io::Result<Vec<_>>>()
+            .context("Failed to record a staged path")?;
+        write_atomically(&self.dir.join("MANIFEST"), &manifest::serialize(&records))?;
+
+        self.journal.record(&Entry::Staged)?;
+        Ok(records)
+    }
+
+    /// # Description
+    /// Checks that every staged path can be committed
+    ///
+    /// Staged directories may only land on directories (or symlinks to them), and staged files may
+    /// not land on directories
+    fn verify(&self) -> Result<()> {
+        let staging = self.staging();
+        let mut empty = true;
+
+        for entry in staged(&staging) {
+            let entry = entry?;
+            empty = false;
+
+            let target = self.target(&staging, entry.path())?;
+            let Ok(meta) = target.symlink_metadata() else { continue };
+
+            if entry.file_type().is_dir() {
+                if !target.is_dir() {
+                    bail!(
+                        "Refusing to replace '{}' with a directory",
+                        target.display()
+                    );
+                }
+            } else if meta.is_dir() {
+                bail!(
+                    "Refusing to replace directory '{}' with a file",
+                    target.display()
+                );
+            }
+        }
+
+        if empty {
+            bail!("The dist tarball is empty");
+        }
+        Ok(())
+    }
+
+    /// # Description
+    /// Commits every staged path to the prefix
+    ///
+    /// Existing directories are left alone. Files are first moved next to their target, then
+    /// renamed over it, so a target is never missing or partially written. Modified protected
+    /// files are left alone, and the staged file is committed next to them as ``.new``.
+    fn commit(&mut self) -> Result<()> {
+        let staging = self.staging();
+
+        for entry in staged(&staging) {
+            let entry = entry?;
+            let mut target = self.target(&staging, entry.path())?;
+
+            if !entry.file_type().is_dir() && self.preserves(entry.path(), &target)? {
+                info!("Keeping modified '{}'", target.display());
+                self.preserved.push(target.clone());
+                target = configs::new_path(&target);
+            }
+
+            if entry.file_type().is_dir() {
+                if target.exists() {
+                    continue;
+                }
+                self.journal.record(&Entry::Dir(target.clone()))?;
+                fs::create_dir(&target)
+                    .with_context(|| format!("Failed to create '{}'", target.display()))?;
+                copy_attributes(entry.path(), &target)?;
+                continue;
+            }
+
+            let replacing = target.symlink_metadata().is_ok();
+            if replacing {
+                self.journal.record(&Entry::Replace(target.clone()))?;
+            } else {
+                self.journal.record(&Entry::New(target.clone()))?;
+            }
+
+            let new = sibling(&target, "new")?;
+            move_entry(entry.path(), &new)?;
+            if replacing {
+                backup(&target)?;
+            }
+            fs::rename(&new, &target)
+                .with_context(|| format!("Failed to install '{}'", target.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// # Description
+    /// Records what was actually installed for preserved files in the manifest
+    ///
+    /// A preserved file was left alone, so its record describes the kept file. Its new version is
+    /// recorded as ``.new`` right after it, so it's removed along with the package, and so the kept
+    /// file is still treated as modified by later installs (see ``preserves()``).
+    fn record_preserved(&self, records: &mut Vec<Record>) -> Result<()> {
+        if self.preserved.is_empty() {
+            return Ok(());
+        }
+
+        for target in &self.preserved {
+            let path = format!("/{}", target.strip_prefix(&self.prefix)?.display());
+            let Some(i) = records.iter().position(|r| r.path == path) else { continue };
+
+            let mut new = Record::new(&path, target)?;
+            std::mem::swap(&mut records[i], &mut new);
+            new.path = configs::new_path(Path::new(&path))
+                .to_string_lossy()
+                .to_string();
+            records.insert(i + 1, new);
+        }
+
+        write_atomically(&self.dir.join("MANIFEST"), &manifest::serialize(records))
+    }
+
+    /// # Description
+    /// Removes dead paths from the installed version (see ``remove::logic::dead_files()``)
+    ///
+    /// Paths the staged files replaced aren't dead, and are skipped. Files are renamed to their
+    /// backups, so they can be restored if the transaction is rolled back. Directories are left
+    /// for ``finalize()``, since the backups may still be in them.
+    fn remove_dead(&mut self, dead: &[(Record, PathBuf)], staged: &[Record]) -> Result<()> {
+        let staged = staged
+            .iter()
+            .map(|r| r.path.as_str())
+            .collect::<HashSet<_>>();
+
+        for (record, path) in dead {
+            if staged.contains(record.path.as_str()) {
+                continue;
+            }
+            let Ok(meta) = path.symlink_metadata() else { continue };
+
+            self.journal.record(&Entry::Remove(path.clone()))?;
+            if !meta.is_dir() {
+                let old = sibling(path, "old")?;
+                fs::rename(path, &old)
+                    .with_context(|| format!("Failed to remove '{}'", path.display()))?;
+            }
+            self.removed.push(record.clone());
+        }
+
+        Ok(())
+    }
+
+    /// # Description
+    /// Returns true if the file at ``target`` should be kept instead of replaced by a staged file
+    ///
+    /// This is the case if the target is protected, differs from the staged file, and was
+    /// modified since it was installed. Protected files that weren't installed by this package, or
+    /// that were kept over a previous version (see ``record_preserved()``), are treated as
+    /// modified.
+    fn preserves(&self, staged: &Path, target: &Path) -> Result<bool> {
+        let rel = target.strip_prefix(&self.prefix)?;
+        if !configs::is_protected(rel, &self.protected) || target.symlink_metadata().is_err() {
+            return Ok(false);
+        }
+
+        let path = format!("/{}", rel.display());
+        let new = Record::new(&path, staged)?;
+        if !configs::is_modified(&new, target) {
+            return Ok(false);
+        }
+
+        let kept = configs::new_path(Path::new(&path));
+        Ok(self.installed.contains_key(&*kept.to_string_lossy())
+            || self
+                .installed
+                .get(&path)
+                .is_none_or(|old| configs::is_modified(old, target)))
+    }
+
+    /// # Description
+    /// Marks the transaction as committed and finalizes it
+    fn finish(mut self) -> Result<()> {
+        self.journal.record(&Entry::Commit)?;
+        finalize(&self.dir)
+    }
+
+    /// # Description
+    /// Returns where a staged path is committed to
+    fn target(&self, staging: &Path, path: &Path) -> Result<PathBuf> {
+        Ok(self.prefix.join(path.strip_prefix(staging)?))
+    }
+}
+
+/// # Description
+/// Walks the staging directory such that directories come before their contents
+fn staged(staging: &Path) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> {
+    WalkDir::new(staging)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+}
+
+/// # Description
+/// Returns the path next to ``path`` used to hold its new or old version during a transaction
+fn sibling(path: &Path, tag: &str) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("Invalid path '{}'", path.display()))?;
+    Ok(path.with_file_name(format!(".2-{tag}.{}", name.to_string_lossy())))
+}
+
+/// # Description
+/// Copies ownership, permissions, and the modification time from one path to another
+///
+/// Ownership is set first, since changing it clears setuid and setgid bits. Only ownership is
+/// copied for symlinks.
+fn copy_attributes(from: &Path, to: &Path) -> Result<()> {
+    let meta = from.symlink_metadata()?;
+    lchown(to, Some(meta.uid()), Some(meta.gid()))
+        .with_context(|| format!("Failed to chown '{}'", to.display()))?;
+
+    if meta.is_symlink() {
+        return Ok(());
+    }
+
+    fs::set_permissions(to, fs::Permissions::from_mode(meta.mode()))?;
+    if meta.is_file() {
+        File::options()
+            .write(true)
+            .open(to)?
+            .set_modified(meta.modified()?)?;
+    }
+    Ok(())
+}
+
+/// # Description
+/// Copies a file or symlink, preserving its attributes
+///
+/// The copy is synced to disk
+fn copy_entry(from: &Path, to: &Path) -> Result<()> {
+    let meta = from.symlink_metadata()?;
+
+    if meta.is_symlink() {
+        symlink(fs::read_link(from)?, to)?;
+    } else if meta.is_file() {
+        fs::copy(from, to)?;
+    } else {
+        bail!("Unsupported file type for '{}'", from.display());
+    }
+
+    copy_attributes(from, to)?;
+    if meta.is_file() {
+        File::open(to)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// # Description
+/// Moves a staged file or symlink to ``to``, copying it if ``to`` is on another filesystem
+fn move_entry(from: &Path, to: &Path) -> Result<()> {
+    remove_if_exists(to)?;
+
+    match fs::rename(from, to) {
+        | Ok(()) => {
+            if to.symlink_metadata()?.is_file() {
+                File::open(to)?.sync_all()?;
+            }
+            Ok(())
+        },
+        | Err(e) if e.kind() == IOE::CrossesDevices => copy_entry(from, to),
+        | Err(e) => Err(e).with_context(|| format!("Failed to move '{}'", from.display())),
+    }
+}
+
+/// # Description
+/// Backs up a path before it's replaced
+///
+/// Hard links are used where possible. Otherwise, the path is copied, and the copy is renamed into
+/// place, so a backup is never partial.
+fn backup(path: &Path) -> Result<()> {
+    let old = sibling(path, "old")?;
+    remove_if_exists(&old)?;
+
+    if fs::hard_link(path, &old).is_ok() {
+        return Ok(());
+    }
+
+    let tmp = sibling(path, "tmp")?;
+    remove_if_exists(&tmp)?;
+    copy_entry(path, &tmp)?;
+    fs::rename(&tmp, &old).with_context(|| format!("Failed to back up '{}'", path.display()))
+}
+
+/// # Description
+/// Removes a file or symlink, ignoring missing ones
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        | Err(e) if e.kind() != IOE::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove '{}'", path.display()))
+        },
+        | _ => Ok(()),
+    }
+}
+
+/// # Description
+/// Writes a file by writing a temporary file and renaming it over the original
+fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let tmp = sibling(path, "tmp")?;
+    let mut f = File::create(&tmp)?;
+    f.write_all(contents.as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write '{}'", path.display()))
+}
+
+/// # Description
+/// Undoes a transaction according to its journal, then removes it
+///
+/// Entries are undone in reverse. Replaced and removed files are restored from their backups, and
+/// new files and directories are removed. Entries whose change was never made are harmless to undo.
+#[instrument]
+fn rollback(dir: &Path) -> Result<()> {
+    let entries = Journal::read(&dir.join("JOURNAL"))?;
+
+    for entry in entries.iter().rev() {
+        debug!("Undoing '{entry}'");
+        match entry {
+            | Entry::Dir(p) => {
+                if let Err(e) = fs::remove_dir(p) {
+                    warn!("Failed to remove directory '{}': {e}", p.display());
+                }
+            },
+            | Entry::New(p) => {
+                remove_if_exists(&sibling(p, "new")?)?;
+                remove_if_exists(p)?;
+            },
+            | Entry::Replace(p) => {
+                remove_if_exists(&sibling(p, "new")?)?;
+                remove_if_exists(&sibling(p, "tmp")?)?;
+
+                let old = sibling(p, "old")?;
+                if old.symlink_metadata().is_ok() {
+                    fs::rename(&old, p)
+                        .with_context(|| format!("Failed to restore '{}'", p.display()))?;
+                }
+            },
+            | Entry::Remove(p) => {
+                let old = sibling(p, "old")?;
+                if old.symlink_metadata().is_ok() {
+                    fs::rename(&old, p)
+                        .with_context(|| format!("Failed to restore '{}'", p.display()))?;
+                }
+            },
+            | _ => {},
+        }
+    }
+
+    fs::remove_dir_all(dir).context("Failed to remove the transaction")
+}
+
+/// # Description
+/// Finishes a committed transaction
+///
+/// Writes the manifest and the installed version, records the package in the database, removes
+/// backups and empty dead directories, and removes the transaction. This is safe to repeat if it
+/// was interrupted.
+#[instrument]
+fn finalize(dir: &Path) -> Result<()> {
+    let entries = Journal::read(&dir.join("JOURNAL"))?;
+    let Some(Entry::Begin { relpath, version }) = entries.first() else {
+        bail!("The journal is missing its BEGIN entry");
+    };
+
+    let manifest =
+        fs::read_to_string(dir.join("MANIFEST")).context("Failed to read the manifest")?;
+
+    let data = Path::new("/var/ports").join(relpath).join(".data");
+    write_atomically(&data.join(format!("MANIFEST={version}")), &manifest)?;
+    write_atomically(&data.join("INSTALLED"), &format!("{version}\n"))?;
+    db::record_install(relpath, version, &manifest::parse(&manifest)?)?;
+
+    // dead paths are recorded with files before the directories containing them
+    for entry in &entries {
+        match entry {
+            | Entry::Remove(p) if p.symlink_metadata().is_ok_and(|m| m.is_dir()) => {
+                if let Err(e) = fs::remove_dir(p) {
+                    debug!("Keeping dead directory '{}': {e}", p.display());
+                }
+            },
+            | Entry::Replace(p) | Entry::Remove(p) => remove_if_exists(&sibling(p, "old")?)?,
+            | _ => {},
+        }
+    }
+
+    fs::remove_dir_all(dir).context("Failed to remove the transaction")
+}
+
+/// # Description
+/// Runs ldconfig and the post-install steps
+fn run_hooks(package: &Package) -> Result<()> {
+    exec("ldconfig", None).context("Failed to run ldconfig")?;
+    script::post(package)
+}
+
+/// # Description
+/// Installs a package's dist tarball transactionally
+///
+/// The dist tarball is staged, checked for conflicts with installed packages (see
+/// ``conflicts::check()``), and verified, then committed. Dead paths from the installed version
+/// are removed as part of the transaction. If anything fails, the transaction is rolled back,
+/// leaving the system as it was.
+///
+/// ldconfig and the post-install steps run once the transaction is committed. They can't be rolled
+/// back, so if they fail, the package stays installed and the failure is returned.
+///
+/// Protected files modified since the installed version was installed are kept (see
+/// ``package::configs``).
+///
+/// Returns the records of the dead paths that were removed
+///
+/// **Error conditions:**
+/// - a previous transaction couldn't be recovered
+/// - the transaction failed (and was rolled back)
+/// - rolling back failed
+/// - the post-install steps failed
+#[instrument(skip(package, dead))]
+pub fn install(package: &Package, dead: &[(Record, PathBuf)]) -> Result<Vec<Record>> {
+    recover()?;
+
+    // computed first, so an invalid pattern doesn't leave a transaction behind
+    let protected = configs::patterns()?;
+
+    let dir = Path::new(TRANSACTION_DIR);
+    let prefix = Path::new(&CONFIG.general.prefix);
+    let mut t = Transaction::begin(dir, prefix, package)?;
+    t.protect(protected, installed_records(package));
+
+    let result = t.stage(&package.data.dist).and_then(|mut records| {
+        conflicts::check(package, &records)?;
+        t.verify()?;
+        t.commit()?;
+        t.record_preserved(&mut records)?;
+        t.remove_dead(dead, &records)
+    });
+
+    if let Err(e) = result {
+        warn!("Transaction for '{package}' failed: {e:#}");
+        erm!("Installing '{package}' failed; rolling back");
+        rollback(dir).context("Failed to roll back the transaction")?;
+        return Err(e);
+    }
+
+    let preserved = std::mem::take(&mut t.preserved);
+    let removed = std::mem::take(&mut t.removed);
+    t.finish()?;
+
+    for p in &preserved {
+        pr!(
+            "'{}' was modified; installed as '{}'",
+            p.display(),
+            configs::new_path(p).display()
+        );
+    }
+    if !preserved.is_empty() {
+        msg!(
+            "󰒓  {} configuration files are pending; run '2 --merge' to merge them",
+            preserved.len()
+        );
+    }
+
+    run_hooks(package)
+        .with_context(|| format!("Installed '{package}', but its post-install steps failed"))?;
+    Ok(removed)
+}
+
+/// # Description
+/// Reads the records of a package's installed version
+///
+/// An unreadable manifest is treated as empty, which protects every protected file
+fn installed_records(package: &Package) -> Vec<Record> {
+    if !package.data.is_installed {
+        return Vec::new();
+    }
+
+    let manifest = package
+        .data
+        .port_dir
+        .join(".data")
+        .join(format!("MANIFEST={}", package.data.installed_version));
+
+    manifest::read(&manifest).unwrap_or_else(|e| {
+        warn!("Failed to read the installed manifest for '{package}': {e:#}");
+        Vec::new()
+    })
+}
+
+/// # Description
+/// Recovers from an interrupted transaction, if there is one
+///
+/// Committed transactions are resumed, and all others are rolled back
+pub fn recover() -> Result<()> {
+    let dir = Path::new(TRANSACTION_DIR);
+    let journal = dir.join("JOURNAL");
+    if !journal.exists() {
+        return Ok(());
+    }
+
+    let entries = Journal::read(&journal)?;
+    let package = match entries.first() {
+        | Some(Entry::Begin { relpath, version }) => format!("{relpath}={version}"),
+        | _ => "an unknown package".to_string(),
+    };
+
+    if entries.last() == Some(&Entry::Commit) {
+        msg!("󰁯  Resuming interrupted install of '{package}'...");
+        info!("Resuming interrupted install of '{package}'");
+        finalize(dir)
+    } else {
+        msg!("󰁯  Rolling back interrupted install of '{package}'...");
+        info!("Rolling back interrupted install of '{package}'");
+        rollback(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::Path,
+    };
+
+    use glob::Pattern;
+
+    use super::{
+        Entry,
+        Journal,
+        Transaction,
+        rollback,
+    };
+    use crate::{
+        package::Package,
+        remove::manifest::{
+            self,
+            Record,
+        },
+        utils::{
+            fail::Fail,
+            testing::{
+                self,
+                Scratch,
+            },
+        },
+    };
+
+    fn package() -> Package { testing::package("tree", "2.2.1", "") }
+
+    #[test]
+    fn journal_entries_round_trip() {
+        let entries = [
+            Entry::Begin {
+                relpath: "main/tree".to_string(),
+                version: "2.2.1".to_string(),
+            },
+            Entry::Staged,
+            Entry::Dir("/usr/share/tree".into()),
+            Entry::New("/usr/bin/tree".into()),
+            Entry::Replace("/usr/share/man/man1/tree.1".into()),
+            Entry::Remove("/usr/bin/tree-old".into()),
+            Entry::Commit,
+        ];
+
+        for entry in entries {
+            assert_eq!(entry.to_string().parse::<Entry>().ok(), Some(entry));
+        }
+    }
+
+    #[test]
+    fn rollback_restores_the_prefix() {
+        let root = Scratch::new("rollback");
+        let dir = root.join("transaction");
+        let prefix = root.join("prefix");
+
+        fs::create_dir_all(prefix.join("usr/bin")).fail("Failed to create prefix");
+        fs::create_dir_all(prefix.join("usr/lib/tree")).fail("Failed to create prefix");
+        fs::write(prefix.join("usr/bin/tree"), "old").fail("Failed to write file");
+        fs::write(prefix.join("usr/bin/tree-old"), "dead").fail("Failed to write file");
+        let dead = ["/usr/bin/tree-old", "/usr/lib/tree", "/usr/bin/tree"].map(|p| {
+            let path = prefix.join(p.trim_start_matches('/'));
+            (Record::new(p, &path).fail("Failed to record"), path)
+        });
+        let staged =
+            [Record::new("/usr/bin/tree", &prefix.join("usr/bin/tree")).fail("Failed to record")];
+
+        let mut t = Transaction::begin(&dir, &prefix, &package()).fail("Failed to begin");
+        let staging = t.staging();
+        fs::create_dir_all(staging.join("usr/bin")).fail("Failed to stage");
+        fs::create_dir_all(staging.join("usr/share/tree")).fail("Failed to stage");
+        fs::write(staging.join("usr/bin/tree"), "new").fail("Failed to stage");
+        fs::write(staging.join("usr/share/tree/README"), "new").fail("Failed to stage");
+
+        t.verify().fail("Failed to verify");
+        t.commit().fail("Failed to commit");
+        assert_eq!(
+            fs::read_to_string(prefix.join("usr/bin/tree"))
+                .ok()
+                .as_deref(),
+            Some("new")
+        );
+
+        // paths the new version installs aren't dead, and dead directories are left for finalizing
+        t.remove_dead(&dead, &staged)
+            .fail("Failed to remove dead paths");
+        assert!(!prefix.join("usr/bin/tree-old").exists());
+        assert!(prefix.join("usr/bin/tree").exists());
+        assert!(prefix.join("usr/lib/tree").exists());
+        assert_eq!(t.removed, [dead[0].0.clone(), dead[1].0.clone()]);
+        assert!(Journal::read(&dir.join("JOURNAL")).is_ok_and(|e| e.len() == 7));
+
+        rollback(&dir).fail("Failed to roll back");
+        assert_eq!(
+            fs::read_to_string(prefix.join("usr/bin/tree"))
+                .ok()
+                .as_deref(),
+            Some("old")
+        );
+        assert_eq!(
+            fs::read_to_string(prefix.join("usr/bin/tree-old"))
+                .ok()
+                .as_deref(),
+            Some("dead")
+        );
+        assert!(!Path::new(&prefix.join("usr/share")).exists());
+        assert!(!prefix.join("usr/bin/.2-old.tree").exists());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn files_may_not_replace_directories() {
+        let root = Scratch::new("verify");
+        let dir = root.join("transaction");
+        let prefix = root.join("prefix");
+
+        fs::create_dir_all(prefix.join("usr/lib/tree")).fail("Failed to create prefix");
+
+        let t = Transaction::begin(&dir, &prefix, &package()).fail("Failed to begin");
+        fs::create_dir_all(t.staging().join("usr/lib")).fail("Failed to stage");
+        fs::write(t.staging().join("usr/lib/tree"), "file").fail("Failed to stage");
+
+        assert!(t.verify().is_err());
+    }
+
+    #[test]
+    fn modified_configs_are_installed_as_new() {
+        let root = Scratch::new("configs");
+        let dir = root.join("transaction");
+        let prefix = root.join("prefix");
+
+        fs::create_dir_all(prefix.join("etc")).fail("Failed to create prefix");
+        fs::write(prefix.join("etc/tree.conf"), "original").fail("Failed to write file");
+        fs::write(prefix.join("etc/tree.rc"), "original").fail("Failed to write file");
+        let installed = ["tree.conf", "tree.rc"].map(|f| {
+            Record::new(&format!("/etc/{f}"), &prefix.join("etc").join(f)).fail("Failed to record")
+        });
+        fs::write(prefix.join("etc/tree.conf"), "edited").fail("Failed to write file");
+
+        let mut t = Transaction::begin(&dir, &prefix, &package()).fail("Failed to begin");
+        t.protect(
+            vec![Pattern::new("etc").fail("Invalid pattern")],
+            installed.to_vec(),
+        );
+        fs::create_dir_all(t.staging().join("etc")).fail("Failed to stage");
+        fs::write(t.staging().join("etc/tree.conf"), "new").fail("Failed to stage");
+        fs::write(t.staging().join("etc/tree.rc"), "new").fail("Failed to stage");
+        let mut records = ["tree.conf", "tree.rc"]
+            .map(|f| {
+                Record::new(&format!("/etc/{f}"), &t.staging().join("etc").join(f))
+                    .fail("Failed to record")
+            })
+            .to_vec();
+
+        t.commit().fail("Failed to commit");
+        let read = |f: &str| fs::read_to_string(prefix.join(f)).ok();
+        assert_eq!(read("etc/tree.conf").as_deref(), Some("edited"));
+        assert_eq!(read("etc/tree.conf.new").as_deref(), Some("new"));
+        assert_eq!(read("etc/tree.rc").as_deref(), Some("new"));
+        assert!(!prefix.join("etc/tree.rc.new").exists());
+        assert_eq!(t.preserved, [prefix.join("etc/tree.conf")]);
+
+        // the kept file is recorded as is, followed by its new version
+        t.record_preserved(&mut records)
+            .fail("Failed to record preserved files");
+        let manifest = fs::read_to_string(dir.join("MANIFEST")).fail("Failed to read manifest");
+        let written = manifest::parse(&manifest).fail("Failed to parse manifest");
+        let kept = Record::new("/etc/tree.conf", &prefix.join("etc/tree.conf")).fail("Failed");
+        assert_eq!(written, records);
+        assert_eq!(written[0].hash, kept.hash);
+        assert_eq!(written[1].path, "/etc/tree.conf.new");
+        assert_eq!(written[2].path, "/etc/tree.rc");
+
+        // and stays preserved on the next install, even though its record matches
+        let mut next =
+            Transaction::begin(&root.join("next"), &prefix, &package()).fail("Failed to begin");
+        next.protect(vec![Pattern::new("etc").fail("Invalid pattern")], written);
+        let target = prefix.join("etc/tree.conf");
+        assert!(
+            next.preserves(&prefix.join("etc/tree.conf.new"), &target)
+                .fail("Failed")
+        );
+
+        rollback(&dir).fail("Failed to roll back");
+        assert!(!prefix.join("etc/tree.conf.new").exists());
+        assert_eq!(read("etc/tree.rc").as_deref(), Some("original"));
+    }
+}
@@ -3,6 +3,8 @@
 
 use clap::Parser;
 
+use crate::package::files::Only;
+
 #[derive(Parser, Debug)]
 #[allow(clippy::struct_excessive_bools)]
 #[command(
@@ -40,6 +42,13 @@ pub struct Args {
     #[arg(short = 'r', long)]
     pub remove: bool,
 
+    /// Removes orphans
+    ///
+    /// Orphans are packages installed as dependencies that no installed package depends on anymore.
+    /// Removal is repeated until no orphans remain.
+    #[arg(short = 'O', long)]
+    pub autoremove: bool,
+
     /// Updates packages
     ///
     /// If combined with force, bypasses latest version checks
@@ -92,6 +101,31 @@ pub struct Args {
     #[arg(short = 's', long)]
     pub stats:      bool,
 
+    /// Displays installed packages that depend on a package
+    #[arg(short = 'R', long)]
+    pub rdeps: bool,
+
+    /// Lists the files owned by installed packages
+    ///
+    /// Files are read from the manifest for the installed version
+    #[arg(short = 'F', long)]
+    pub files: bool,
+
+    /// Restricts the files listed by files
+    #[arg(long, value_enum, value_name = "KIND")]
+    pub only: Option<Only>,
+
+    /// Displays the files listed by files as a tree
+    #[arg(long)]
+    pub tree: bool,
+
+    /// Verifies installed files against package manifests
+    ///
+    /// Reports files that were modified or are missing. Verifies all installed packages if none
+    /// are passed.
+    #[arg(short = 'k', long)]
+    pub verify: bool,
+
     /// Retrieves upstream versions for packages
     #[cfg(feature = "upstream")]
     #[arg(short = 'U', long)]
@@ -119,9 +153,42 @@ pub struct Args {
     pub add_repos: Vec<String>,
 
     /// See which packages provide a path
+    ///
+    /// Absolute paths match exactly, names without a slash match basenames, and globs match full
+    /// paths (or basenames, if they contain no slash)
     #[arg(short = 'P', long, value_name = "PATH", value_delimiter = ' ', num_args = 1..)]
     pub provides: Vec<String>,
 
+    /// Searches packages in all repos
+    ///
+    /// Each argument is a case-insensitive regex matched against names, descriptions, categories,
+    /// and upstreams
+    #[arg(short = 'Q', long, value_name = "REGEX", value_delimiter = ' ', num_args = 1..)]
+    pub search: Vec<String>,
+
+    /// Interprets the arguments to provides as regexes
+    #[arg(short = 'E', long)]
+    pub regex: bool,
+
+    /// Lists pending configuration files
+    ///
+    /// These are new versions of protected files that were modified, installed as ``.new``
+    #[arg(short = 'N', long)]
+    pub list_new: bool,
+
+    /// Merges pending configuration files
+    ///
+    /// Shows the changes for each, and prompts whether to keep the current file or use the new
+    /// one. Merges all pending files if none are passed.
+    #[arg(short = 'M', long, value_name = "PATH", value_delimiter = ' ', num_args = 0..)]
+    pub merge: Option<Vec<String>>,
+
+    /// Rebuilds the installed package database from /var/ports
+    ///
+    /// Takes no arguments
+    #[arg(short = 'D', long)]
+    pub rebuild_db: bool,
+
     /// The positional argument on which most flags act
     #[arg(value_name = "PACKAGE", num_args = 0.., value_delimiter = ' ')]
     pub packages: Vec<String>,
@@ -137,10 +204,27 @@ pub struct Args {
     #[arg(short = 'q', long)]
     pub quiet: bool,
 
+    /// Prints machine-readable JSON for queries
+    ///
+    /// Affects list, about, long-about, stats, upstream, provides, and search, which print one
+    /// JSON object per line
+    #[arg(short = 'j', long)]
+    pub json: bool,
+
     /// Forces actions, useful with other flags
     #[arg(short = 'f', long)]
     pub force: bool,
 
+    /// Skips confirmation before building, installing, updating, or removing packages
+    #[arg(short = 'y', long)]
+    pub yes: bool,
+
+    /// Shows what would be done without doing it
+    ///
+    /// Applies to install, build, update, remove, and autoremove. Other package flags are ignored.
+    #[arg(short = 'n', long)]
+    pub pretend: bool,
+
     /// Displays the version
     #[arg(short = 'V', long)]
     pub version: bool,
@@ -150,4 +234,35 @@ impl Args {
     /// ### Description
     /// Parses command line arguments
     pub fn init() -> Self { Self::parse() }
+
+    /// ### Description
+    /// Returns true if any of the passed arguments require root privileges
+    ///
+    /// Only installing, updating, and removing packages, merging configuration files, and
+    /// rebuilding the database touch the system. Builds drop to the build user when run as root,
+    /// but don't need root themselves. Nothing is installed, updated, or removed when pretending.
+    pub const fn needs_root(&self) -> bool {
+        (!self.pretend && (self.install || self.update || self.remove || self.autoremove))
+            || self.merge.is_some()
+            || self.rebuild_db
+    }
+
+    /// ### Description
+    /// Returns true if any of the passed arguments build, install, update, or remove packages,
+    /// which are confirmed first unless yes is passed
+    pub const fn needs_confirmation(&self) -> bool {
+        self.install || self.build || self.update || self.remove || self.autoremove
+    }
+
+    /// ### Description
+    /// Returns true if none of the passed arguments modify anything
+    pub const fn is_read_only(&self) -> bool {
+        !(self.needs_root()
+            || self.build
+            || self.get
+            || self.prune
+            || self.clean
+            || self.sync_repos.is_some())
+            && self.add_repos.is_empty()
+    }
 }
@@ -0,0 +1,390 @@
+// src/db/mod.rs
+//! The installed package database
+//!
+//! The database indexes installed packages, their installed versions, and the paths they own, so
+//! lookups don't have to walk /var/ports and read every manifest. It's derived entirely from each
+//! port's ``.data``, so it can always be rebuilt (see ``rebuild()``), and is rebuilt automatically
+//! if it's missing.
+//!
+//! ```text
+//! #2 db v1
+//! main/tree  2.2.1
+//!   d  /usr/bin
+//!   f  /usr/bin/tree
+//! ```
+//!
+//! Fields are separated by tabs (shown as spaces above). Each package is followed by the paths it
+//! owns, each indented with a tab and prefixed with its type (see ``remove::manifest::Kind``).
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        ErrorKind as IOE,
+        Write,
+    },
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
+use tracing::{
+    info,
+    instrument,
+    warn,
+};
+
+use crate::{
+    remove::manifest::{
+        self,
+        Kind,
+        Record,
+    },
+    shell::user,
+    utils::{
+        comms::msg,
+        fail::Fail,
+        time::Stopwatch,
+    },
+};
+
+/// # Description
+/// Where the database is stored
+const DB: &str = "/var/lib/2/db";
+
+/// # Description
+/// Where ports are scanned for when rebuilding the database
+const PORTS: &str = "/var/ports";
+
+/// # Description
+/// The first line of the database
+const HEADER: &str = "#2 db v1";
+
+/// # Description
+/// An installed package's entry in the database
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Installed {
+    pub version: String,
+    pub paths:   Vec<(Kind, String)>,
+}
+
+impl Installed {
+    /// # Description
+    /// Creates an entry for a version from its manifest's records
+    pub fn new(version: &str, records: &[Record]) -> Self {
+        Self {
+            version: version.to_string(),
+            paths:   records.iter().map(|r| (r.kind, r.path.clone())).collect(),
+        }
+    }
+}
+
+/// # Description
+/// The installed package database
+///
+/// Packages are keyed by relpath ('repo/name'). Paths are indexed by owner.
+#[derive(Debug, Default)]
+pub struct Db {
+    packages: BTreeMap<String, Installed>,
+    owners:   HashMap<String, Vec<String>>,
+}
+
+impl Db {
+    /// # Description
+    /// Loads the database
+    ///
+    /// If it doesn't exist, it's rebuilt from /var/ports, and saved if running as root
+    ///
+    /// **Error conditions:**
+    /// - the database couldn't be read or is invalid
+    /// - the database was missing, and rebuilding it failed
+    #[instrument]
+    pub fn load() -> Result<Self> {
+        match fs::read_to_string(DB) {
+            | Ok(contents) => Self::parse(&contents).context("Invalid database"),
+            | Err(e) if e.kind() == IOE::NotFound => {
+                info!("Database missing; rebuilding it");
+                let db = Self::scan(Path::new(PORTS))?;
+                if user::is_root() {
+                    db.save()?;
+                }
+                Ok(db)
+            },
+            | Err(e) => Err(e).context("Failed to read the database"),
+        }
+    }
+
+    /// # Description
+    /// Parses the database
+    fn parse(contents: &str) -> Result<Self> {
+        let mut lines = contents.lines();
+        if lines.next() != Some(HEADER) {
+            bail!("Unknown database format");
+        }
+
+        let mut db = Self::default();
+        let mut current: Option<(&str, Installed)> = None;
+
+        for line in lines.filter(|l| !l.is_empty()) {
+            if let Some(rest) = line.strip_prefix('\t') {
+                let (_, installed) = current.as_mut().context("Path without a package")?;
+                let (kind, path) = rest
+                    .split_once('\t')
+                    .with_context(|| format!("Malformed path '{rest}'"))?;
+                installed
+                    .paths
+                    .push((Kind::from_symbol(kind)?, path.to_string()));
+                continue;
+            }
+
+            if let Some((relpath, installed)) = current.take() {
+                db.insert(relpath, installed);
+            }
+            let (relpath, version) = line
+                .split_once('\t')
+                .with_context(|| format!("Malformed package '{line}'"))?;
+            current = Some((relpath, Installed {
+                version: version.to_string(),
+                paths:   Vec::new(),
+            }));
+        }
+
+        if let Some((relpath, installed)) = current {
+            db.insert(relpath, installed);
+        }
+        Ok(db)
+    }
+
+    /// # Description
+    /// Serializes the database
+    fn serialize(&self) -> String {
+        let mut lines = vec![HEADER.to_string()];
+        for (relpath, installed) in &self.packages {
+            lines.push(format!("{relpath}\t{}", installed.version));
+            lines.extend(
+                installed
+                    .paths
+                    .iter()
+                    .map(|(kind, path)| format!("\t{}\t{path}", kind.symbol())),
+            );
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// # Description
+    /// Writes the database to disk
+    ///
+    /// A temporary file is written, synced, and renamed over the database, so the database is
+    /// never partially written
+    pub fn save(&self) -> Result<()> {
+        let path = Path::new(DB);
+        let tmp = path.with_extension("tmp");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create /var/lib/2")?;
+        }
+
+        let mut f = File::create(&tmp).context("Failed to create the database")?;
+        f.write_all(self.serialize().as_bytes())
+            .context("Failed to write the database")?;
+        f.sync_all().context("Failed to sync the database")?;
+        fs::rename(&tmp, path).context("Failed to save the database")
+    }
+
+    /// # Description
+    /// Builds a database from the installed ports under ``ports``
+    ///
+    /// Ports are installed if ``.data/INSTALLED`` exists, and own the paths in the manifest for
+    /// the installed version. Ports with missing or invalid manifests own no paths.
+    #[instrument]
+    fn scan(ports: &Path) -> Result<Self> {
+        let mut db = Self::default();
+        let visible = |e: &fs::DirEntry| !e.file_name().to_string_lossy().starts_with('.');
+
+        let repos = fs::read_dir(ports).context("Failed to read /var/ports")?;
+        for repo in repos.flatten().filter(visible) {
+            let Ok(ports) = fs::read_dir(repo.path()) else { continue };
+            for port in ports.flatten().filter(visible) {
+                let data = port.path().join(".data");
+                let Ok(version) = fs::read_to_string(data.join("INSTALLED")) else { continue };
+                let version = version.trim();
+
+                let relpath = format!(
+                    "{}/{}",
+                    repo.file_name().to_string_lossy(),
+                    port.file_name().to_string_lossy()
+                );
+                let records = manifest::read(&data.join(format!("MANIFEST={version}")))
+                    .unwrap_or_else(|e| {
+                        warn!("Indexing '{relpath}' without paths: {e:#}");
+                        Vec::new()
+                    });
+
+                db.insert(&relpath, Installed::new(version, &records));
+            }
+        }
+
+        Ok(db)
+    }
+
+    /// # Description
+    /// Records a package as installed, replacing any previous entry
+    pub fn insert(&mut self, relpath: &str, installed: Installed) {
+        self.remove(relpath);
+        for (_, path) in &installed.paths {
+            self.owners
+                .entry(path.clone())
+                .or_default()
+                .push(relpath.to_string());
+        }
+        self.packages.insert(relpath.to_string(), installed);
+    }
+
+    /// # Description
+    /// Records a package as no longer installed
+    pub fn remove(&mut self, relpath: &str) {
+        let Some(old) = self.packages.remove(relpath) else { return };
+        for (_, path) in &old.paths {
+            if let Some(owners) = self.owners.get_mut(path) {
+                owners.retain(|o| o != relpath);
+                if owners.is_empty() {
+                    self.owners.remove(path);
+                }
+            }
+        }
+    }
+
+    /// # Description
+    /// Returns true if a package is installed
+    pub fn is_installed(&self, relpath: &str) -> bool { self.packages.contains_key(relpath) }
+
+    /// # Description
+    /// Returns the installed packages and their entries, sorted by relpath
+    pub fn packages(&self) -> impl Iterator<Item = (&str, &Installed)> {
+        self.packages.iter().map(|(r, i)| (r.as_str(), i))
+    }
+
+    /// # Description
+    /// Returns the installed packages that own a path
+    pub fn owners(&self, path: &str) -> &[String] {
+        self.owners.get(path).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// # Description
+/// Records a package as installed at a version owning some paths, and saves the database
+pub fn record_install(relpath: &str, version: &str, records: &[Record]) -> Result<()> {
+    let mut db = Db::load()?;
+    db.insert(relpath, Installed::new(version, records));
+    db.save()
+}
+
+/// # Description
+/// Records a package as removed, and saves the database
+pub fn record_removal(relpath: &str) -> Result<()> {
+    let mut db = Db::load()?;
+    db.remove(relpath);
+    db.save()
+}
+
+/// # Description
+/// Rebuilds the database from /var/ports
+pub fn rebuild() {
+    let mut stopwatch = Stopwatch::new();
+    stopwatch.start();
+    msg!("󰑓  Rebuilding the database...");
+
+    let db = Db::scan(Path::new(PORTS)).fail("Failed to scan /var/ports");
+    db.save().fail("Failed to save the database");
+
+    msg!(
+        "󰑓  Indexed {} packages and {} paths in {}",
+        db.packages.len(),
+        db.owners.len(),
+        stopwatch.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{
+        Db,
+        Installed,
+    };
+    use crate::{
+        remove::manifest::Kind,
+        utils::{
+            fail::Fail,
+            testing::Scratch,
+        },
+    };
+
+    fn installed(version: &str, paths: &[&str]) -> Installed {
+        Installed {
+            version: version.to_string(),
+            paths:   paths
+                .iter()
+                .map(|p| (Kind::File, (*p).to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn tracks_owners_across_installs_and_removals() {
+        let mut db = Db::default();
+        db.insert("main/vim", installed("9.1", &["/usr/bin", "/usr/bin/vim"]));
+        db.insert(
+            "main/tree",
+            installed("2.2", &["/usr/bin", "/usr/bin/tree"]),
+        );
+        assert_eq!(db.owners("/usr/bin"), ["main/vim", "main/tree"]);
+
+        db.insert("main/vim", installed("9.2", &["/usr/bin", "/usr/bin/vi"]));
+        assert!(db.owners("/usr/bin/vim").is_empty());
+        assert_eq!(db.owners("/usr/bin/vi"), ["main/vim"]);
+
+        let parsed = Db::parse(&db.serialize()).fail("Failed to parse database");
+        assert_eq!(
+            parsed
+                .packages()
+                .map(|(r, i)| (r, i.version.as_str()))
+                .collect::<Vec<_>>(),
+            [("main/tree", "2.2"), ("main/vim", "9.2")]
+        );
+        assert_eq!(parsed.owners("/usr/bin"), ["main/tree", "main/vim"]);
+
+        db.remove("main/vim");
+        assert!(!db.is_installed("main/vim"));
+        assert_eq!(db.owners("/usr/bin"), ["main/tree"]);
+    }
+
+    #[test]
+    fn scans_installed_manifests() {
+        let ports = Scratch::new("db");
+        let data = ports.join("main/tree/.data");
+        fs::create_dir_all(&data).fail("Failed to create port");
+        fs::create_dir_all(ports.join("main/vim/.data")).fail("Failed to create port");
+        fs::create_dir_all(ports.join("main/.sets")).fail("Failed to create sets");
+        fs::write(data.join("INSTALLED"), "2.2\n").fail("Failed to write");
+        fs::write(data.join("MANIFEST=2.2"), "/usr/bin/tree\n").fail("Failed to write");
+        fs::write(data.join("MANIFEST=2.1"), "/usr/bin/old\n").fail("Failed to write");
+
+        let db = Db::scan(&ports).fail("Failed to scan");
+        assert_eq!(db.packages().map(|(r, _)| r).collect::<Vec<_>>(), [
+            "main/tree"
+        ]);
+        assert_eq!(db.owners("/usr/bin/tree"), ["main/tree"]);
+        assert!(db.owners("/usr/bin/old").is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+// src/fetch/cache.rs
+//! A content-addressed cache for sources, shared between ports
+//!
+//! When enabled, downloaded sources are moved to /var/cache/2/sources, named by their hash (see
+//! ``utils::hash``), and symlinked back into the port's ``.sources``. Since every LOCK records the
+//! hash of each source, a source is only downloaded once, no matter how many ports use it.
+//!
+//! Cached sources no LOCK references are pruned once they haven't been used for
+//! ``fetch.cache_max_age`` days.
+
+use std::{
+    collections::HashSet,
+    fs::{
+        self,
+        File,
+    },
+    io::ErrorKind as IOE,
+    os::unix::fs::symlink,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use tracing::warn;
+
+use crate::{
+    globals::{
+        config::CONFIG,
+        flags::Flags,
+    },
+    package::parse::expand_set,
+    utils::{
+        comms::pr,
+        hash::try_twohash,
+    },
+};
+
+/// # Description
+/// Where cached sources are stored
+const DIR: &str = "/var/cache/2/sources";
+
+/// # Description
+/// Returns true if the cache is enabled
+pub fn enabled() -> bool { CONFIG.fetch.cache }
+
+/// # Description
+/// Returns true if a source with a hash is cached
+pub fn contains(hash: &str) -> bool {
+    enabled() && !hash.is_empty() && Path::new(DIR).join(hash).is_file()
+}
+
+/// # Description
+/// Links a cached source to ``out``, returning false if it isn't cached
+///
+/// **Error conditions:**
+/// - ``out`` couldn't be replaced with a symlink
+pub fn link(hash: &str, out: &Path) -> Result<bool> {
+    if !enabled() || hash.is_empty() {
+        return Ok(false);
+    }
+    link_in(Path::new(DIR), hash, out)
+}
+
+/// # Description
+/// Moves a downloaded source into the cache and links it back to where it was
+///
+/// Sources whose hash doesn't match aren't cached, and false is returned
+///
+/// **Error conditions:**
+/// - the source couldn't be hashed, moved, or linked
+pub fn store(out: &Path, hash: &str) -> Result<bool> {
+    if !enabled() || hash.is_empty() {
+        return Ok(false);
+    }
+    store_in(Path::new(DIR), out, hash)
+}
+
+/// # Description
+/// Prunes cached sources that no LOCK references and haven't been used recently
+///
+/// Returns the number of pruned sources
+pub fn prune() -> usize {
+    if !enabled() {
+        return 0;
+    }
+
+    let packages = expand_set("//@@");
+    let referenced = packages
+        .iter()
+        .flat_map(|p| std::iter::once(&p.source).chain(p.extra.iter()))
+        .map(|s| s.hash.as_str())
+        .collect::<HashSet<_>>();
+    let max_age = Duration::from_hours(CONFIG.fetch.cache_max_age.saturating_mul(24));
+
+    let pruned = prune_in(Path::new(DIR), &referenced, max_age);
+    if !Flags::grab().quiet {
+        for path in &pruned {
+            pr!("Pruning '{}'", path.display());
+        }
+    }
+    pruned.len()
+}
+
+/// # Description
+/// Links a cached source in ``dir`` to ``out``, marking it as used
+fn link_in(dir: &Path, hash: &str, out: &Path) -> Result<bool> {
+    let cached = dir.join(hash);
+    if !cached.is_file() {
+        return Ok(false);
+    }
+
+    replace_with_link(&cached, out)?;
+    touch(&cached);
+    Ok(true)
+}
+
+/// # Description
+/// Moves a source into the cache in ``dir`` and links it back to where it was
+fn store_in(dir: &Path, out: &Path, hash: &str) -> Result<bool> {
+    if try_twohash(out).context("Failed to hash source")? != hash {
+        return Ok(false);
+    }
+
+    fs::create_dir_all(dir).context("Failed to create the source cache")?;
+    let cached = dir.join(hash);
+    if fs::rename(out, &cached).is_err() {
+        // the cache may be on a different filesystem, so copy it, then rename it into place
+        let tmp = cached.with_extension("tmp");
+        fs::copy(out, &tmp).context("Failed to copy source to the cache")?;
+        fs::rename(&tmp, &cached).context("Failed to move source into the cache")?;
+        fs::remove_file(out).context("Failed to remove the uncached source")?;
+    }
+
+    replace_with_link(&cached, out)?;
+    touch(&cached);
+    Ok(true)
+}
+
+/// # Description
+/// Removes sources in ``dir`` that aren't referenced and are older than ``max_age``
+///
+/// Age is measured from when a source was last stored or linked
+fn prune_in(dir: &Path, referenced: &HashSet<&str>, max_age: Duration) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let now = SystemTime::now();
+
+    let mut pruned = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if referenced.contains(&*entry.file_name().to_string_lossy()) {
+            continue;
+        }
+
+        let age = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .map(|t| now.duration_since(t).unwrap_or_default())
+            .unwrap_or_default();
+        if age < max_age {
+            continue;
+        }
+
+        match fs::remove_file(&path) {
+            | Ok(()) => pruned.push(path),
+            | Err(e) => warn!("Failed to prune cached source '{}': {e}", path.display()),
+        }
+    }
+    pruned
+}
+
+/// # Description
+/// Replaces whatever is at ``out`` with a symlink to ``cached``
+fn replace_with_link(cached: &Path, out: &Path) -> Result<()> {
+    match fs::remove_file(out) {
+        | Err(e) if e.kind() != IOE::NotFound => {
+            return Err(e).with_context(|| format!("Failed to replace '{}'", out.display()));
+        },
+        | _ => {},
+    }
+    symlink(cached, out).with_context(|| format!("Failed to link '{}'", out.display()))
+}
+
+/// # Description
+/// Marks a cached source as used, so it isn't pruned too soon
+fn touch(cached: &Path) {
+    if let Err(e) = File::options()
+        .write(true)
+        .open(cached)
+        .and_then(|f| f.set_modified(SystemTime::now()))
+    {
+        warn!("Failed to mark '{}' as used: {e}", cached.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        fs,
+        time::Duration,
+    };
+
+    use super::{
+        link_in,
+        prune_in,
+        store_in,
+    };
+    use crate::utils::{
+        fail::Fail,
+        hash::twohash,
+        testing::Scratch,
+    };
+
+    #[test]
+    fn stores_links_and_prunes_sources() {
+        let root = Scratch::new("cache");
+        let cache = root.join("cache");
+        let sources = root.join("sources");
+        fs::create_dir_all(&sources).fail("Failed to create sources");
+
+        let out = sources.join("tree=2.2.1.tar.bz2");
+        fs::write(&out, "tree").fail("Failed to write");
+        let hash = twohash(&out);
+
+        assert!(!store_in(&cache, &out, "mismatched").fail("Failed to store"));
+        assert!(!out.is_symlink());
+
+        assert!(store_in(&cache, &out, &hash).fail("Failed to store"));
+        assert!(out.is_symlink());
+        assert_eq!(fs::read_to_string(&out).fail("Failed to read"), "tree");
+
+        let alias = sources.join("tree-alias=2.2.1.tar.bz2");
+        assert!(link_in(&cache, &hash, &alias).fail("Failed to link"));
+        assert!(!link_in(&cache, "uncached", &alias).fail("Failed to link"));
+        assert_eq!(
+            fs::read_link(&alias).fail("Failed to read link"),
+            cache.join(&hash)
+        );
+
+        let referenced = HashSet::from([hash.as_str()]);
+        assert!(prune_in(&cache, &referenced, Duration::ZERO).is_empty());
+        assert!(prune_in(&cache, &HashSet::new(), Duration::from_hours(1)).is_empty());
+        assert_eq!(prune_in(&cache, &HashSet::new(), Duration::ZERO), [
+            cache.join(&hash)
+        ]);
+    }
+}
@@ -2,12 +2,19 @@
 //! Defines download functions
 
 use std::{
-    fs::File,
+    fs::{
+        self,
+        OpenOptions,
+    },
     io::{
         Read,
         Write,
     },
-    path::Path,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
 };
 
 use anyhow::{
@@ -16,22 +23,44 @@ use anyhow::{
     bail,
 };
 use indicatif::{
+    MultiProgress,
     ProgressBar,
     ProgressStyle,
 };
+#[cfg(feature = "parallelism")]
+use rayon::prelude::*;
 use ureq::{
+    Body,
     Error as UE,
-    http::header::CONTENT_LENGTH,
+    http::{
+        Response,
+        StatusCode,
+        header::{
+            CONTENT_LENGTH,
+            RANGE,
+        },
+    },
 };
 
+use super::{
+    cache,
+    mirrors::MIRRORS,
+};
+#[cfg(feature = "parallelism")]
+use crate::utils::par::build_download_pool;
 use crate::{
-    package::Package,
+    globals::config::CONFIG,
+    package::{
+        Package,
+        PackageSource,
+    },
     utils::{
-        comms::vpr,
-        fail::{
-            BoolFail,
-            Fail,
+        comms::{
+            erm,
+            vpr,
         },
+        fail::Fail,
+        hash::try_twohash,
     },
 };
 
@@ -42,15 +71,47 @@ pub enum DownloadStatus {
     Both,
 }
 
+/// # Description
+/// Downloads sources for several packages concurrently
+///
+/// Up to ``fetch.max_downloads`` packages are downloaded at once, with a bar for each source. A
+/// failed download doesn't stop the others. Results are returned in the order the packages were
+/// passed.
+pub fn download_all<'p>(
+    packages: &[&'p Package],
+    force: bool,
+    sty: &ProgressStyle,
+) -> Vec<(&'p Package, Result<DownloadStatus>)> {
+    let bars = MultiProgress::new();
+    let get = |p: &&'p Package| (*p, download(p, force, &bars, sty));
+
+    #[cfg(not(feature = "parallelism"))]
+    let results = packages.iter().map(get).collect();
+
+    #[cfg(feature = "parallelism")]
+    let results =
+        build_download_pool(packages.len()).install(|| packages.par_iter().map(get).collect());
+
+    results
+}
+
 /// # Description
 /// Very high level download function for package
 ///
-/// Downloads the tarball and any extra sources
-pub fn download(package: &Package, force: bool, sty: &ProgressStyle) -> DownloadStatus {
-    let tb = download_tarball(package, force, sty);
-    let ex = download_extra(package, force, sty);
+/// Downloads the tarball and any extra sources, adding their bars to ``bars``
+///
+/// **Error conditions:**
+/// - the tarball or an extra source failed to download
+pub fn download(
+    package: &Package,
+    force: bool,
+    bars: &MultiProgress,
+    sty: &ProgressStyle,
+) -> Result<DownloadStatus> {
+    let tb = download_tarball(package, force, bars, sty)?;
+    let ex = download_extra(package, force, bars, sty)?;
 
-    if ex && tb {
+    Ok(if ex && tb {
         DownloadStatus::Both
     } else if ex {
         DownloadStatus::Extra
@@ -58,7 +119,7 @@ pub fn download(package: &Package, force: bool, sty: &ProgressStyle) -> Download
         DownloadStatus::Tarball
     } else {
         DownloadStatus::Nothing
-    }
+    })
 }
 
 /// # Description
@@ -68,29 +129,29 @@ pub fn download(package: &Package, force: bool, sty: &ProgressStyle) -> Download
 /// Affected by force
 ///
 /// **Fail conditions:**
-/// - source path could not be created
 /// - url was invalid
-/// - ``download_url()`` returns an error other than Exists
+///
+/// **Error conditions:**
+/// - ``download_verified()`` returns an error other than Exists
 ///
 /// Saves the downloaded sources to ``/var/ports/<repo>/<package>/.sources/<name>``
-pub fn download_extra(package: &Package, force: bool, sty: &ProgressStyle) -> bool {
-    let mut dlct = 0;
-    package.extra.iter().for_each(|source| {
+pub fn download_extra(
+    package: &Package,
+    force: bool,
+    bars: &MultiProgress,
+    sty: &ProgressStyle,
+) -> Result<bool> {
+    for source in &*package.extra {
         let url = &source.url;
-        let file_name = url
-            .rsplit_once('/')
-            .map(|(_, name)| name.to_string())
-            .efail(|| format!("Invalid extra url '{url}' for '{package}'"));
-        let out = package.data.port_dir.join(".sources").join(&file_name);
-
-        if let Err(e) = download_url(&source.url, &out, force, sty) {
-            e.to_string()
-                .contains("Exists: ")
-                .or_efail(|| format!("Failed to get extra url '{url}' for '{package}'"));
+        let out = extra_path(package, url);
+
+        if let Err(e) = download_source(source, &out, force, bars, sty)
+            && !e.to_string().contains("Exists: ")
+        {
+            return Err(e).with_context(|| format!("Failed to get extra url '{url}'"));
         }
-        dlct += 1;
-    });
-    dlct >= 1
+    }
+    Ok(!package.extra.is_empty())
 }
 
 /// # Description
@@ -99,12 +160,24 @@ pub fn download_extra(package: &Package, force: bool, sty: &ProgressStyle) -> bo
 /// Downloads a specific url to an output destination; that output destination
 /// must be manually specified, and for 2, is usually in .sources
 ///
+/// The download is written to ``<out>.part``, which is renamed to ``out`` once complete, so an
+/// interrupted download never leaves a truncated file behind. If a ``.part`` exists, the download
+/// is resumed from where it left off with a range request. Servers that don't support ranges
+/// send the whole file, which overwrites the ``.part``.
+///
 /// **Error conditions:**
-/// - the output path exists and force is not passed. Will overwrite if force is passed.
-/// - the http status is not 200
+/// - the output path exists and force is not passed. Will overwrite if force is passed, discarding
+///   any partial download.
+/// - the http status is not 200 (or 206 when resuming)
 /// - the file path cannot be created (unlikely)
 /// - random buffer-related rw failures (unlikely)
-pub fn download_url(url: &str, out: &Path, force: bool, sty: &ProgressStyle) -> Result<String> {
+pub fn download_url(
+    url: &str,
+    out: &Path,
+    force: bool,
+    bars: &MultiProgress,
+    sty: &ProgressStyle,
+) -> Result<String> {
     let file_name = out
         .file_name()
         .context("Failed to get filename")?
@@ -116,9 +189,26 @@ pub fn download_url(url: &str, out: &Path, force: bool, sty: &ProgressStyle) ->
         bail!("Exists: {:?}", file_path);
     }
 
+    let part = file_path.with_file_name(format!("{file_name}.part"));
+    if force && part.exists() {
+        fs::remove_file(&part).context("Failed to discard partial download")?;
+    }
+    let mut offset = fs::metadata(&part).map_or(0, |m| m.len());
+
     vpr!("Downloading '{url}'...");
+    let r = match request(url, offset) {
+        | Err(UE::StatusCode(416)) if offset > 0 => {
+            // the partial download is larger than the file, so it's stale
+            vpr!("Discarding stale partial download '{}'", part.display());
+            fs::remove_file(&part).context("Failed to discard partial download")?;
+            offset = 0;
+            request(url, offset)
+        },
+        | r => r,
+    };
+
     #[rustfmt::skip]
-    let r = match ureq::get(url).call() {
+    let r = match r {
         | Ok(r) => r,
         | Err(UE::StatusCode(code)) => bail!("Received status code '{code}'"),
         | Err(UE::HostNotFound)     => bail!("Failed to resolve hostname"),
@@ -126,25 +216,37 @@ pub fn download_url(url: &str, out: &Path, force: bool, sty: &ProgressStyle) ->
     };
     vpr!("Response:\n{r:#?}");
 
-    let length: u64 = r
-        .headers()
-        .get(CONTENT_LENGTH)
-        .and_then(|hv| hv.to_str().ok())
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(8192);
+    let resumed = offset > 0 && r.status() == StatusCode::PARTIAL_CONTENT;
+    if resumed {
+        vpr!("Resuming '{file_name}' from {offset} bytes");
+    } else {
+        offset = 0;
+    }
 
-    let pb = ProgressBar::new(length);
+    let length: u64 = offset
+        + r.headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|hv| hv.to_str().ok())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(8192);
+
+    let pb = bars.add(ProgressBar::new(length));
     pb.set_style(sty.clone());
     pb.set_length(length);
     pb.set_prefix("󰇚 ");
     pb.set_message(file_name.clone());
 
-    let mut f = File::create(file_path)?;
+    let mut f = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part)?;
 
     let body = r.into_body();
     let reader = body.into_reader();
 
-    let mut downloaded = 0;
+    let mut downloaded = offset;
     let mut reader = pb.wrap_read(reader);
     let mut buffer = vec![0; 8192];
 
@@ -164,6 +266,9 @@ pub fn download_url(url: &str, out: &Path, force: bool, sty: &ProgressStyle) ->
         }
     }
 
+    f.sync_all()?;
+    fs::rename(&part, file_path).context("Failed to move the download into place")?;
+
     pb.set_position(length);
     pb.set_prefix("󰄹 ");
     pb.finish_with_message(file_name.clone());
@@ -171,6 +276,111 @@ pub fn download_url(url: &str, out: &Path, force: bool, sty: &ProgressStyle) ->
     Ok(file_name)
 }
 
+/// # Description
+/// Downloads a url with ``download_url()``, then checks it against its known hash
+///
+/// Hashes are only checked if ``check_hashes`` is enabled. If the hash doesn't match, the
+/// download is deleted, the expected and actual hashes are reported, and it's retried up to
+/// ``fetch.retries`` times.
+///
+/// **Error conditions:**
+/// - ``download_url()`` returns an error
+/// - the download couldn't be hashed or deleted (unlikely)
+/// - the hash still doesn't match after every retry
+fn download_verified(
+    url: &str,
+    out: &Path,
+    hash: &str,
+    force: bool,
+    bars: &MultiProgress,
+    sty: &ProgressStyle,
+) -> Result<String> {
+    let mut retries = 0;
+    loop {
+        let file_name = download_url(url, out, force, bars, sty)?;
+        if !CONFIG.general.check_hashes {
+            return Ok(file_name);
+        }
+
+        let actual = try_twohash(out).context("Failed to hash the download")?;
+        if actual == hash {
+            return Ok(file_name);
+        }
+
+        fs::remove_file(out).context("Failed to delete the mismatched download")?;
+        bars.suspend(|| {
+            erm!(
+                "Hash mismatch for '{file_name}' from '{url}'\n  expected: {hash}\n  actual:   {actual}"
+            );
+        });
+
+        if retries == CONFIG.fetch.retries {
+            bail!("Hash still mismatched after {retries} retries");
+        }
+        retries += 1;
+        vpr!("Retrying '{url}' ({retries}/{})...", CONFIG.fetch.retries);
+    }
+}
+
+/// # Description
+/// Downloads a source, falling back to its mirrors and fallback urls
+///
+/// If the source cache is enabled and has the source, it's linked instead of downloaded (unless
+/// force is passed), and sources that are downloaded are stored in it (see ``cache``).
+///
+/// Urls are tried in the order given by ``Mirrors::urls()``, each with ``download_verified()``,
+/// until one succeeds
+///
+/// **Error conditions:**
+/// - the output path exists and force is not passed
+/// - the source couldn't be linked from or stored in the cache
+/// - every url failed
+fn download_source(
+    source: &PackageSource,
+    out: &Path,
+    force: bool,
+    bars: &MultiProgress,
+    sty: &ProgressStyle,
+) -> Result<String> {
+    if out.exists() && !force {
+        bail!("Exists: {:?}", out);
+    }
+
+    if !force && cache::link(&source.hash, out)? {
+        vpr!("Linked cached source '{}'", out.display());
+        return out
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .context("Failed to get filename");
+    }
+
+    let urls = MIRRORS.urls(source);
+    let mut urls = urls.iter().peekable();
+    while let Some(url) = urls.next() {
+        match download_verified(url, out, &source.hash, force, bars, sty) {
+            | Ok(file_name) => {
+                cache::store(out, &source.hash).context("Failed to cache source")?;
+                return Ok(file_name);
+            },
+            | Err(e) if urls.peek().is_some() => {
+                bars.suspend(|| erm!("Failed to download '{url}': {e:#}\nTrying the next url..."));
+            },
+            | Err(e) => return Err(e).with_context(|| format!("Failed to download '{url}'")),
+        }
+    }
+    bail!("No urls for source")
+}
+
+/// # Description
+/// Sends a GET request for a url, asking for everything after ``offset`` if it's nonzero
+fn request(url: &str, offset: u64) -> Result<Response<Body>, UE> {
+    let mut req = ureq::get(url);
+    if offset > 0 {
+        req = req.header(RANGE, format!("bytes={offset}-"));
+    }
+    req.call()
+}
+
 /// # Description
 /// Normalizes tarball extensions to their long form. This is used to calculate
 /// the tarball file name in ``download_tarball()``. It's also used to calculate
@@ -213,15 +423,35 @@ pub fn normalize_tarball(package: &Package, tarball: &str) -> String {
 /// Affected by force
 ///
 /// **Fail conditions:**
-/// - source path could not be created
 /// - url was invalid
-/// - ``download_url()`` returns an error other than Exists
+///
+/// **Error conditions:**
+/// - ``download_verified()`` returns an error other than Exists
 ///
 /// Saves the downloaded sources to ``/var/ports/<repo>/<package>/.sources/<name>``
-fn download_tarball(package: &Package, force: bool, sty: &ProgressStyle) -> bool {
-    let url = package.source.url.clone();
+fn download_tarball(
+    package: &Package,
+    force: bool,
+    bars: &MultiProgress,
+    sty: &ProgressStyle,
+) -> Result<bool> {
+    let url = &package.source.url;
+    let Some(out) = tarball_path(package) else { return Ok(false) };
+
+    vpr!("Downloading tarball...");
+    match download_source(&package.source, &out, force, bars, sty) {
+        | Ok(_) => Ok(true),
+        | Err(e) if e.to_string().contains("Exists: ") => Ok(false),
+        | Err(e) => Err(e).with_context(|| format!("Failed to download tarball from '{url}'")),
+    }
+}
+
+/// # Description
+/// Returns where a package's tarball is saved, or None if it has no source url
+fn tarball_path(package: &Package) -> Option<PathBuf> {
+    let url = &package.source.url;
     if url.is_empty() {
-        return false;
+        return None;
     }
 
     let file_name = url
@@ -231,15 +461,44 @@ fn download_tarball(package: &Package, force: bool, sty: &ProgressStyle) -> bool
         .efail(|| format!("Invalid url '{url}' for '{package}'"));
     let file_name = normalize_tarball(package, file_name);
 
-    let srcpath = package.data.port_dir.join(".sources");
-    let out = srcpath.join(file_name);
+    Some(package.data.port_dir.join(".sources").join(file_name))
+}
 
-    vpr!("Downloading tarball...");
-    if let Err(e) = download_url(&url, &out, force, sty) {
-        e.to_string()
-            .contains("Exists: ")
-            .or_efail(|| format!("Failed to download tarball from '{url}' for '{package}'"));
-        return false;
-    }
-    true
+/// # Description
+/// Returns where an extra source for a package is saved
+fn extra_path(package: &Package, url: &str) -> PathBuf {
+    let file_name = url
+        .rsplit_once('/')
+        .map(|(_, name)| name.to_string())
+        .efail(|| format!("Invalid extra url '{url}' for '{package}'"));
+    package.data.port_dir.join(".sources").join(file_name)
+}
+
+/// # Description
+/// Returns the urls of a package's sources that haven't been downloaded or cached
+pub fn missing(package: &Package) -> Vec<Arc<str>> {
+    let tarball = tarball_path(package).map(|out| (&package.source, out));
+    let extra = package
+        .extra
+        .iter()
+        .map(|s| (s, extra_path(package, &s.url)));
+
+    tarball
+        .into_iter()
+        .chain(extra)
+        .filter(|(s, out)| !out.exists() && !cache::contains(&s.hash))
+        .map(|(s, _)| s.url.clone())
+        .collect()
+}
+
+/// # Description
+/// Returns the size of a remote file from the Content-Length of a HEAD request
+///
+/// Returns None if the request failed or the server didn't say
+pub fn remote_size(url: &str) -> Option<u64> {
+    let r = ureq::head(url).call().ok()?;
+    r.headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.parse().ok())
 }
@@ -0,0 +1,158 @@
+// src/fetch/mirrors.rs
+//! Reads mirrors for sources from /etc/2/mirrors.toml
+//!
+//! ```toml
+//! [gnu]
+//! prefix  = "https://ftp.gnu.org/gnu/"
+//! mirrors = ["https://ftpmirror.gnu.org/gnu/", "https://mirrors.kernel.org/gnu/"]
+//! ```
+//!
+//! A url starting with a prefix may also be downloaded from each of its mirrors, in order, by
+//! replacing the prefix with the mirror. If several prefixes match, the longest one is used.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind as IOE,
+    iter,
+    sync::LazyLock,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde::Deserialize;
+
+use crate::{
+    package::PackageSource,
+    utils::fail::Fail,
+};
+
+/// # Description
+/// Where mirrors are read from
+const FILE: &str = "/etc/2/mirrors.toml";
+
+/// # Description
+/// The mirrors, loaded on first use
+pub static MIRRORS: LazyLock<Mirrors> =
+    LazyLock::new(|| Mirrors::load().fail("Failed to load /etc/2/mirrors.toml"));
+
+/// # Description
+/// A url prefix and its mirrors
+#[derive(Deserialize, Debug)]
+struct Mirror {
+    prefix:  String,
+    mirrors: Vec<String>,
+}
+
+/// # Description
+/// Every mirror, keyed by name
+#[derive(Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct Mirrors(BTreeMap<String, Mirror>);
+
+impl Mirrors {
+    /// # Description
+    /// Loads the mirrors from /etc/2/mirrors.toml
+    ///
+    /// A missing file means there are no mirrors
+    ///
+    /// **Error conditions:**
+    /// - the file couldn't be read or is invalid
+    fn load() -> Result<Self> {
+        match fs::read_to_string(FILE) {
+            | Ok(content) => Self::parse(&content),
+            | Err(e) if e.kind() == IOE::NotFound => Ok(Self::default()),
+            | Err(e) => Err(e).context("Failed to read /etc/2/mirrors.toml"),
+        }
+    }
+
+    /// # Description
+    /// Parses mirrors
+    fn parse(content: &str) -> Result<Self> { toml::from_str(content).context("Invalid mirrors") }
+
+    /// # Description
+    /// Returns the mirrored urls for a url, in order
+    fn mirror(&self, url: &str) -> Vec<String> {
+        self.0
+            .values()
+            .filter(|m| url.starts_with(&m.prefix))
+            .max_by_key(|m| m.prefix.len())
+            .map(|m| {
+                let rest = &url[m.prefix.len()..];
+                m.mirrors
+                    .iter()
+                    .map(|mirror| format!("{mirror}{rest}"))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// # Description
+    /// Returns every url a source may be downloaded from, in the order they should be tried
+    ///
+    /// The source's url comes first, followed by its mirrors, then each of the source's fallback
+    /// urls, each followed by its mirrors. Duplicates are dropped.
+    pub fn urls(&self, source: &PackageSource) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for url in iter::once(&source.url).chain(&source.fallbacks) {
+            for candidate in iter::once(url.to_string()).chain(self.mirror(url)) {
+                if !urls.contains(&candidate) {
+                    urls.push(candidate);
+                }
+            }
+        }
+        urls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mirrors;
+    use crate::{
+        package::PackageSource,
+        utils::fail::Fail,
+    };
+
+    #[test]
+    fn expands_urls_with_mirrors() {
+        let mirrors = Mirrors::parse(
+            r#"
+            [gnu]
+            prefix  = "https://ftp.gnu.org/gnu/"
+            mirrors = ["https://ftpmirror.gnu.org/gnu/", "https://mirrors.kernel.org/gnu/"]
+
+            [gnu-tar]
+            prefix  = "https://ftp.gnu.org/gnu/tar/"
+            mirrors = ["https://example.org/tar/"]
+            "#,
+        )
+        .fail("Failed to parse mirrors");
+
+        let source = PackageSource {
+            url:       "https://ftp.gnu.org/gnu/sed/sed-4.9.tar.xz".into(),
+            fallbacks: vec![
+                "https://mirrors.kernel.org/gnu/sed/sed-4.9.tar.xz".into(),
+                "https://example.com/sed-4.9.tar.xz".into(),
+            ],
+            hash:      String::new(),
+        };
+        assert_eq!(mirrors.urls(&source), [
+            "https://ftp.gnu.org/gnu/sed/sed-4.9.tar.xz",
+            "https://ftpmirror.gnu.org/gnu/sed/sed-4.9.tar.xz",
+            "https://mirrors.kernel.org/gnu/sed/sed-4.9.tar.xz",
+            "https://example.com/sed-4.9.tar.xz",
+        ]);
+
+        // the longest prefix wins
+        let source = PackageSource {
+            url: "https://ftp.gnu.org/gnu/tar/tar-1.35.tar.xz".into(),
+            ..Default::default()
+        };
+        assert_eq!(mirrors.urls(&source), [
+            "https://ftp.gnu.org/gnu/tar/tar-1.35.tar.xz",
+            "https://example.org/tar/tar-1.35.tar.xz",
+        ]);
+    }
+}
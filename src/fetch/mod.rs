@@ -1,4 +1,6 @@
 // src/fetch/mod.rs
 //! Functions for fetching sources
 
+pub mod cache;
 pub mod download;
+pub mod mirrors;
@@ -29,6 +29,8 @@ pub struct Config {
     pub message:  MessageConfig,
     pub removal:  RemovalConfig,
     pub general:  GeneralConfig,
+    #[serde(default)]
+    pub fetch:    FetchConfig,
     #[cfg(feature = "upstream")]
     pub upstream: UpstreamConfig,
 }
@@ -37,6 +39,9 @@ pub struct Config {
 /// Part of the config struct
 ///
 /// General config options
+///
+/// Options added after 2=0.1.0 have defaults matching the shipped config, so older configs still
+/// load
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Deserialize, Debug)]
 pub struct GeneralConfig {
@@ -47,11 +52,37 @@ pub struct GeneralConfig {
     #[cfg(not(test))]
     pub show_failure_location: bool,
     pub check_hashes: bool,
+    #[serde(default = "enabled")]
+    pub isolate_builds: bool,
+    #[serde(default)]
+    pub build_user: String,
+    #[serde(default = "protected")]
+    pub protected: Vec<String>,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
     pub auto_ambiguity: bool,
     pub log_level: String,
     pub alphabetize: bool,
 }
 
+/// # Description
+/// What to do when installing a package would conflict with an installed package
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Refuse,
+    Warn,
+}
+
+/// # Description
+/// The default for options that are enabled unless configured otherwise
+const fn enabled() -> bool { true }
+
+/// # Description
+/// The default for ``general.protected``
+fn protected() -> Vec<String> { vec!["etc".to_string()] }
+
 /// # Description
 /// Part of the config struct
 ///
@@ -71,10 +102,13 @@ pub struct RemovalConfig {
 ///
 /// Config options for 2 flags
 #[derive(Deserialize, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct FlagsConfig {
     pub force:   bool,
     pub quiet:   bool,
     pub verbose: bool,
+    #[serde(default)]
+    pub yes:     bool,
 }
 
 /// # Description
@@ -92,6 +126,32 @@ pub struct MessageConfig {
     pub verbose: String,
 }
 
+/// # Description
+/// Part of the config struct
+///
+/// Config options for downloading sources
+///
+/// Missing options, or a missing section, default to the shipped config
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct FetchConfig {
+    pub retries:       usize,
+    pub max_downloads: usize,
+    pub cache:         bool,
+    pub cache_max_age: u64,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            retries:       3,
+            max_downloads: 4,
+            cache:         false,
+            cache_max_age: 30,
+        }
+    }
+}
+
 /// # Description
 /// Part of the config struct
 ///
@@ -131,3 +191,69 @@ impl Config {
 
 pub static CONFIG: LazyLock<Arc<Config>> =
     LazyLock::new(|| Arc::new(Config::load().fail("Failed to load /etc/2/config.toml")));
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Config,
+        ConflictPolicy,
+    };
+    use crate::utils::fail::Fail;
+
+    /// The config shipped with 2=0.1.0, before any options with defaults were added
+    const OLD: &str = r#"
+        [flags]
+        force   = false
+        quiet   = false
+        verbose = false
+
+        [message]
+        danger  = ''
+        default = ''
+        message = ''
+        prompt  = ''
+        stderr  = ''
+        stdout  = ''
+        verbose = ''
+
+        [removal]
+        remove_sources  = false
+        remove_dist     = true
+        prune_logs      = true
+        prune_manifests = true
+        prune_dist      = true
+
+        [general]
+        prefix                  = "/"
+        clean_after_build       = false
+        show_bug_report_message = true
+        show_failure_location   = true
+        check_hashes            = true
+        auto_ambiguity          = true
+        log_level               = "info"
+        alphabetize             = true
+
+        [upstream]
+        max_threads = 256
+        stack_size  = 256
+        retries     = 3
+    "#;
+
+    #[test]
+    fn old_configs_default_to_the_shipped_config() {
+        let old: Config = toml::from_str(OLD).fail("Failed to parse the old config");
+        let shipped: Config = toml::from_str(include_str!("../../etc/config.toml"))
+            .fail("Failed to parse the shipped config");
+
+        assert_eq!(old.flags.yes, shipped.flags.yes);
+        assert_eq!(old.general.isolate_builds, shipped.general.isolate_builds);
+        assert_eq!(old.general.build_user, shipped.general.build_user);
+        assert_eq!(old.general.protected, shipped.general.protected);
+        assert_eq!(old.general.on_conflict, ConflictPolicy::Refuse);
+        assert_eq!(old.general.on_conflict, shipped.general.on_conflict);
+        assert_eq!(old.fetch.retries, shipped.fetch.retries);
+        assert_eq!(old.fetch.max_downloads, shipped.fetch.max_downloads);
+        assert_eq!(old.fetch.cache, shipped.fetch.cache);
+        assert_eq!(old.fetch.cache_max_age, shipped.fetch.cache_max_age);
+    }
+}
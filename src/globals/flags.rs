@@ -18,11 +18,14 @@ pub static FLAGS: OnceCell<Flags> = OnceCell::new();
 ///
 /// By default, these are taken from the config, but may be overridden with passed flags.
 #[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Flags {
     pub force:   bool,
     pub quiet:   bool,
     #[allow(dead_code)] // im too lazy to handle it properly with #[cfg(not(test))]
     pub verbose: bool,
+    pub json:    bool,
+    pub yes:     bool,
 }
 
 impl Flags {
@@ -32,8 +35,15 @@ impl Flags {
         let force = args.force || CONFIG.flags.force;
         let quiet = args.quiet || CONFIG.flags.quiet;
         let verbose = args.verbose || CONFIG.flags.verbose;
-
-        Self { force, quiet, verbose }
+        let yes = args.yes || CONFIG.flags.yes;
+
+        Self {
+            force,
+            quiet,
+            verbose,
+            json: args.json,
+            yes,
+        }
     }
 
     pub fn grab() -> &'static Self { FLAGS.get().fail("FLAGS has not been initialized") }
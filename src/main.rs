@@ -18,6 +18,7 @@
 
 mod build;
 mod cli;
+mod db;
 mod fetch;
 mod globals;
 mod package;
@@ -42,12 +43,15 @@ use globals::{
     },
 };
 use package::{
+    configs,
     parse,
     provides,
     repos,
+    search,
     sets,
 };
 use pm::PM;
+use shell::user;
 use tracing::{
     debug,
     info,
@@ -68,7 +72,9 @@ use utils::fail::BoolFail;
 /// Takes arguments from the environment and calls PM or other functions accordingly
 fn main() {
     let (mut args, guard) = initialize();
-    unsafe { libc::geteuid() == 0 }.or_fail("2 requires root privileges");
+    if args.needs_root() {
+        user::is_root().or_fail("2 requires root privileges to modify the system");
+    }
 
     handle_special_args(&mut args);
 
@@ -102,10 +108,17 @@ fn initialize() -> (Args, WorkerGuard) {
 /// ### Description
 /// Initializes logging
 ///
+/// Logs are written to /tmp/2/log, or /tmp/2-$UID/log when not running as root
+///
 /// Log level priority:
 /// Environment variable -> Config -> Default
 fn init_logging() -> WorkerGuard {
-    let file_appender = rolling::never("/tmp/2", "log");
+    let dir = if user::is_root() {
+        "/tmp/2".to_string()
+    } else {
+        format!("/tmp/2-{}", unsafe { libc::geteuid() })
+    };
+    let file_appender = rolling::never(dir, "log");
     let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
 
     let level = LevelFilter::from_str(&CONFIG.general.log_level).unwrap_or(LevelFilter::INFO);
@@ -133,7 +146,10 @@ fn handle_special_args(args: &mut Args) {
         v::display()
     }
 
-    args.provides.iter().for_each(|p| provides::provides(p));
+    args.provides
+        .iter()
+        .for_each(|p| provides::provides(p, args.regex));
+    args.search.iter().for_each(|s| search::search(s));
     args.add_repos.iter().for_each(|r| repos::add(r));
     if let Some(repos) = &mut args.sync_repos {
         if repos.is_empty() {
@@ -146,4 +162,13 @@ fn handle_special_args(args: &mut Args) {
     if args.list_repos {
         repos::list()
     }
+    if args.list_new {
+        configs::list()
+    }
+    if let Some(paths) = &args.merge {
+        configs::merge(paths)
+    }
+    if args.rebuild_db {
+        db::rebuild()
+    }
 }
@@ -1,7 +1,10 @@
 // src/package/ambiguity.rs
 //! Responsible for resolving ambiguity in packages and sets
 
-use std::fs;
+use std::{
+    fs,
+    path::Path,
+};
 
 use tracing::instrument;
 use walkdir::WalkDir;
@@ -53,6 +56,28 @@ fn locate(name: &str) -> Vec<String> {
         .collect()
 }
 
+/// # Description
+/// Given a package, finds its repository without prompting the user
+///
+/// Prefers installed packages, falling back to repo priority. Returns None if no repo contains
+/// the package.
+#[instrument]
+pub fn resolve_ambiguity_quietly(name: &str) -> Option<String> {
+    let mut matches = locate(name);
+    prioritize(&mut matches);
+
+    matches
+        .iter()
+        .find(|m| {
+            Path::new("/var/ports")
+                .join(m)
+                .join(".data/INSTALLED")
+                .exists()
+        })
+        .or_else(|| matches.first())
+        .cloned()
+}
+
 /// # Description
 /// Given a package, finds its repository
 /// Prompts the user if multiple repositories contain the package
@@ -0,0 +1,239 @@
+// src/package/configs.rs
+//! Protects configuration files from being overwritten or removed
+//!
+//! Paths matching a glob in ``protected`` (see the config) are treated as configuration files.
+//! When installing one would overwrite a copy that was modified since it was installed, the new
+//! version is installed next to it as ``<path>.new`` instead, to be merged later. Modified copies
+//! are also kept when packages are removed or updated.
+
+use std::{
+    ffi::OsString,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::Command,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use glob::Pattern;
+use tracing::{
+    instrument,
+    warn,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    build::dist::matches_any,
+    globals::config::CONFIG,
+    remove::manifest::{
+        Kind,
+        Record,
+    },
+    utils::{
+        comms::{
+            erm,
+            msg,
+            pr,
+            select,
+        },
+        fail::Fail,
+    },
+};
+
+/// # Description
+/// Parses the protected globs from the config
+///
+/// Globs are relative to the prefix, and protecting a directory protects everything in it
+pub fn patterns() -> Result<Vec<Pattern>> {
+    CONFIG
+        .general
+        .protected
+        .iter()
+        .map(|p| {
+            let p = p.trim_matches('/');
+            Pattern::new(p).with_context(|| format!("Invalid protected path '{p}'"))
+        })
+        .collect()
+}
+
+/// # Description
+/// Returns true if a path relative to the prefix is protected
+pub fn is_protected(path: &Path, patterns: &[Pattern]) -> bool { matches_any(path, patterns) }
+
+/// # Description
+/// Returns true if two records describe the same contents
+///
+/// Paths of unknown type are never the same as anything, since their contents weren't recorded
+fn same_contents(a: &Record, b: &Record) -> bool {
+    a.kind != Kind::Unknown && a.kind == b.kind && a.hash == b.hash && a.target == b.target
+}
+
+/// # Description
+/// Returns true if the path at ``on_disk`` differs from a record
+///
+/// Only contents are compared; changed permissions alone don't count. Missing paths aren't
+/// considered modified, since there's nothing to protect.
+pub fn is_modified(record: &Record, on_disk: &Path) -> bool {
+    Record::new(&record.path, on_disk).is_ok_and(|found| !same_contents(record, &found))
+}
+
+/// # Description
+/// Returns the path a protected file's new version is installed to
+pub fn new_path(path: &Path) -> PathBuf {
+    let mut new = OsString::from(path);
+    new.push(".new");
+    new.into()
+}
+
+/// # Description
+/// Finds all pending ``.new`` files under protected paths
+#[instrument(skip(patterns))]
+pub fn pending(prefix: &Path, patterns: &[Pattern]) -> Vec<PathBuf> {
+    let mut pending = patterns
+        .iter()
+        .filter_map(|p| glob::glob(&prefix.join(p.as_str()).to_string_lossy()).ok())
+        .flatten()
+        .flatten()
+        .flat_map(|m| WalkDir::new(m).into_iter().flatten())
+        .filter(|e| !e.file_type().is_dir())
+        .map(walkdir::DirEntry::into_path)
+        .filter(|p| {
+            p.file_name()
+                .map(|n| n.to_string_lossy())
+                .is_some_and(|n| n.ends_with(".new") && !n.starts_with(".2-"))
+        })
+        .collect::<Vec<_>>();
+
+    pending.sort();
+    pending.dedup();
+    pending
+}
+
+/// # Description
+/// Lists pending ``.new`` files
+pub fn list() {
+    let prefix = Path::new(&CONFIG.general.prefix);
+    let pending = pending(prefix, &patterns().fail("Invalid protected paths"));
+
+    if pending.is_empty() {
+        return msg!("No pending configuration files");
+    }
+
+    msg!("{} pending configuration files:", pending.len());
+    for p in &pending {
+        pr!("{}", p.display());
+    }
+}
+
+/// # Description
+/// Interactively merges pending ``.new`` files
+///
+/// For each file, the differences are shown, and the user chooses whether to keep the current
+/// file or replace it with the new one. Paths may be passed with or without ``.new``. If none are
+/// passed, all pending files are merged.
+#[instrument]
+pub fn merge(paths: &[String]) {
+    let news = if paths.is_empty() {
+        let prefix = Path::new(&CONFIG.general.prefix);
+        pending(prefix, &patterns().fail("Invalid protected paths"))
+    } else {
+        paths
+            .iter()
+            .map(|p| {
+                let p = PathBuf::from(p);
+                if p.extension().is_some_and(|x| x == "new") { p } else { new_path(&p) }
+            })
+            .collect()
+    };
+
+    if news.is_empty() {
+        return msg!("No pending configuration files");
+    }
+
+    let mut merged = 0;
+    for new in &news {
+        if !new.is_file() {
+            erm!("No pending changes for '{}'", new.display());
+            continue;
+        }
+
+        let current = new.with_extension("");
+        if current.exists() {
+            msg!("Changes to '{}':", current.display());
+            if let Err(e) = Command::new("diff")
+                .arg("-u")
+                .arg(&current)
+                .arg(new)
+                .status()
+            {
+                warn!("Failed to diff '{}': {e}", current.display());
+                erm!("Failed to show changes: {e}");
+            }
+        } else {
+            msg!("'{}' no longer exists", current.display());
+        }
+
+        let choice = select!("[k]eep current, [u]se new, [s]kip");
+        let result = match choice.to_lowercase().as_str() {
+            | "k" | "keep" => fs::remove_file(new),
+            | "u" | "use" => fs::rename(new, &current),
+            | _ => continue,
+        };
+
+        match result {
+            | Ok(()) => merged += 1,
+            | Err(e) => erm!("Failed to merge '{}': {e}", new.display()),
+        }
+    }
+
+    msg!("Merged {merged}/{} configuration files", news.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use glob::Pattern;
+
+    use super::{
+        is_modified,
+        new_path,
+        pending,
+    };
+    use crate::{
+        remove::manifest::Record,
+        utils::{
+            fail::Fail,
+            testing::Scratch,
+        },
+    };
+
+    #[test]
+    fn finds_pending_configs_and_modifications() {
+        let prefix = Scratch::new("configs");
+        fs::create_dir_all(prefix.join("etc/tree")).fail("Failed to create prefix");
+        fs::create_dir_all(prefix.join("usr/bin")).fail("Failed to create prefix");
+
+        let conf = prefix.join("etc/tree/tree.conf");
+        fs::write(&conf, "original").fail("Failed to write file");
+        let record = Record::new("/etc/tree/tree.conf", &conf).fail("Failed to record");
+        assert!(!is_modified(&record, &conf));
+
+        fs::write(&conf, "edited").fail("Failed to write file");
+        assert!(is_modified(&record, &conf));
+        assert!(!is_modified(&Record::legacy("/gone"), &prefix.join("gone")));
+        assert!(is_modified(&Record::legacy("/etc/tree/tree.conf"), &conf));
+
+        fs::write(new_path(&conf), "new").fail("Failed to write file");
+        fs::write(prefix.join("etc/tree/.2-new.tree.conf"), "").fail("Failed to write file");
+        fs::write(prefix.join("usr/bin/tree.new"), "").fail("Failed to write file");
+
+        let patterns = [Pattern::new("etc").fail("Invalid pattern")];
+        assert_eq!(pending(&prefix, &patterns), [new_path(&conf)]);
+    }
+}
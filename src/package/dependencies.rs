@@ -0,0 +1,551 @@
+// src/package/dependencies.rs
+//! Resolves package dependencies
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fmt::{
+        self,
+        Display,
+        Formatter,
+    },
+};
+
+use anyhow::Result;
+use tracing::{
+    debug,
+    instrument,
+};
+
+use super::{
+    Package,
+    ambiguity::{
+        resolve_ambiguity,
+        resolve_ambiguity_quietly,
+    },
+    sets::Set,
+};
+
+/// # Description
+/// The stage a package is built in
+///
+/// Packages in a dependency cycle may declare dependencies they can be built without in LOCK under
+/// ``bootstrap``. Such packages are first built in the bootstrap stage, which exports
+/// ``BOOTSTRAP=1`` to BUILD, and later rebuilt in the full stage once their dependencies exist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Bootstrap,
+    Full,
+}
+
+/// # Description
+/// A dependency cycle, stored as the path through the cycle
+///
+/// The first and last nodes are the same
+#[derive(Debug)]
+struct Cycle(Vec<String>);
+
+impl Display for Cycle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Circular dependency: {}", self.0.join(" -> "))
+    }
+}
+
+impl std::error::Error for Cycle {}
+
+/// # Description
+/// Qualifies a dependency as 'repo/name'
+///
+/// Dependencies may be listed in LOCK either with or without their repo. Unqualified dependencies
+/// are resolved with ``resolve_ambiguity()``, which respects repo priority.
+pub fn qualify(dep: &str) -> String {
+    let dep = dep.split_once('=').map_or(dep, |(d, _)| d);
+    if dep.contains('/') { dep.to_string() } else { resolve_ambiguity(dep) }
+}
+
+/// # Description
+/// Qualifies a dependency as 'repo/name' without prompting the user
+///
+/// This is used for dependencies of installed packages, so installed packages are preferred when a
+/// dependency is ambiguous. Returns the dependency as-is if no repo contains it.
+fn qualify_quietly(dep: &str) -> String {
+    let dep = dep.split_once('=').map_or(dep, |(d, _)| d);
+    if dep.contains('/') {
+        return dep.to_string();
+    }
+    resolve_ambiguity_quietly(dep).unwrap_or_else(|| dep.to_string())
+}
+
+/// # Description
+/// Sorts a dependency graph topologically, starting from the given roots
+///
+/// Nodes are returned such that every node comes after all of its dependencies. The graph is
+/// explored lazily through ``edges()``, which returns the dependencies of a node.
+///
+/// **Error conditions:**
+/// - a cycle is found, in which case the full cycle path is reported
+/// - ``edges()`` returns an error
+fn toposort<F>(roots: &[String], mut edges: F) -> Result<Vec<String>>
+where
+    F: FnMut(&str) -> Result<Vec<String>>,
+{
+    fn visit<F>(
+        node: &str,
+        edges: &mut F,
+        stack: &mut Vec<String>,
+        done: &mut HashSet<String>,
+        order: &mut Vec<String>,
+    ) -> Result<()>
+    where
+        F: FnMut(&str) -> Result<Vec<String>>,
+    {
+        if done.contains(node) {
+            return Ok(());
+        }
+
+        if let Some(i) = stack.iter().position(|n| n == node) {
+            let mut cycle = stack[i..].to_vec();
+            cycle.push(node.to_string());
+            return Err(Cycle(cycle).into());
+        }
+
+        stack.push(node.to_string());
+        for dep in edges(node)? {
+            visit(&dep, edges, stack, done, order)?;
+        }
+        stack.pop();
+
+        done.insert(node.to_string());
+        order.push(node.to_string());
+        Ok(())
+    }
+
+    let mut stack = Vec::new();
+    let mut done = HashSet::new();
+    let mut order = Vec::new();
+
+    for root in roots {
+        visit(root, &mut edges, &mut stack, &mut done, &mut order)?;
+    }
+
+    Ok(order)
+}
+
+/// # Description
+/// Finds an edge in a cycle that can be dropped by bootstrapping
+///
+/// Returns the package to bootstrap and the dependency it can be built without
+fn find_breakable_edge<Q>(
+    cycle: &Cycle,
+    known: &HashMap<String, Package>,
+    qualify: &mut Q,
+) -> Option<(String, String)>
+where
+    Q: FnMut(&str) -> String,
+{
+    cycle.0.windows(2).find_map(|edge| {
+        let [package, dep] = edge else { return None };
+        known
+            .get(package)?
+            .bootstrap
+            .iter()
+            .any(|b| qualify(b) == *dep)
+            .then(|| (package.clone(), dep.clone()))
+    })
+}
+
+/// # Description
+/// Resolves the dependencies for a list of packages
+///
+/// Returns the packages along with all of their (recursive) dependencies in the order they should
+/// be built and installed, meaning dependencies come before their dependents. Dependencies that
+/// are already installed are not recursed into.
+///
+/// Cycles are broken by bootstrapping a package in the cycle that declares it can be built without
+/// the next package in the cycle. A bootstrapped package appears twice: once in the bootstrap
+/// stage, and again in the full stage after the dependencies it was built without.
+///
+/// **Error conditions:**
+/// - the dependency graph contains a cycle that can't be broken
+#[instrument(skip(packages))]
+pub fn resolve(packages: &[Package]) -> Result<Vec<(Package, Stage)>> {
+    resolve_with(
+        packages,
+        |node| {
+            let (repo, name) = node.split_once('/').unwrap_or_default();
+            Package::new(repo, name)
+        },
+        qualify,
+    )
+}
+
+/// # Description
+/// Resolves the dependencies for a list of packages, as ``resolve()`` does
+///
+/// Packages that weren't passed are loaded with ``load()``, and dependencies are qualified with
+/// ``qualify()``
+fn resolve_with<L, Q>(
+    packages: &[Package],
+    mut load: L,
+    mut qualify: Q,
+) -> Result<Vec<(Package, Stage)>>
+where
+    L: FnMut(&str) -> Package,
+    Q: FnMut(&str) -> String,
+{
+    let mut known: HashMap<String, Package> = packages
+        .iter()
+        .map(|p| (p.relpath.clone(), p.clone()))
+        .collect();
+    let requested = packages
+        .iter()
+        .map(|p| p.relpath.clone())
+        .collect::<Vec<_>>();
+    let mut roots = requested.clone();
+    let mut dropped: HashSet<(String, String)> = HashSet::new();
+
+    // ambiguous dependencies prompt the user, so each is only qualified once per resolve
+    let mut qualified: HashMap<String, String> = HashMap::new();
+    let mut qualify = |dep: &str| -> String {
+        qualified
+            .entry(dep.to_string())
+            .or_insert_with(|| qualify(dep))
+            .clone()
+    };
+
+    let order = loop {
+        let sorted = toposort(&roots, |node| {
+            let package = known.entry(node.to_string()).or_insert_with(|| load(node));
+
+            // installed dependencies are already satisfied
+            if package.data.is_installed && !requested.iter().any(|r| r == node) {
+                return Ok(Vec::new());
+            }
+
+            let deps = package
+                .dependencies
+                .iter()
+                .map(|d| qualify(d))
+                .filter(|d| !dropped.contains(&(node.to_string(), d.clone())))
+                .collect::<Vec<_>>();
+            debug!("Dependencies for '{node}': {deps:?}");
+            Ok(deps)
+        });
+
+        match sorted {
+            | Ok(order) => break order,
+            | Err(e) => {
+                let Some(cycle) = e.downcast_ref::<Cycle>() else { return Err(e) };
+                let Some(edge) = find_breakable_edge(cycle, &known, &mut qualify) else {
+                    return Err(e);
+                };
+                debug!(
+                    "Breaking '{cycle}' by bootstrapping '{}' without '{}'",
+                    edge.0, edge.1
+                );
+
+                // the dependency is no longer reached through the dropped edge, so it's resolved
+                // first as a root of its own, placing it between the bootstrap and full stages
+                roots.retain(|r| *r != edge.1);
+                roots.insert(0, edge.1.clone());
+                dropped.insert(edge);
+            },
+        }
+    };
+
+    Ok(stage(order, &dropped)
+        .into_iter()
+        .filter_map(|(node, stage)| known.get(&node).map(|p| (p.clone(), stage)))
+        .collect())
+}
+
+/// # Description
+/// Assigns stages to a sorted list of nodes given the edges that were dropped to sort it
+///
+/// Nodes with dropped edges are bootstrapped in place, and a full stage is inserted after the last
+/// dependency they were built without.
+fn stage(order: Vec<String>, dropped: &HashSet<(String, String)>) -> Vec<(String, Stage)> {
+    let mut staged = order
+        .into_iter()
+        .map(|node| {
+            let stage = if dropped.iter().any(|(p, _)| *p == node) {
+                Stage::Bootstrap
+            } else {
+                Stage::Full
+            };
+            (node, stage)
+        })
+        .collect::<Vec<_>>();
+
+    let bootstrapped = staged
+        .iter()
+        .filter(|(_, s)| *s == Stage::Bootstrap)
+        .map(|(n, _)| n.clone())
+        .collect::<Vec<_>>();
+
+    for node in bootstrapped {
+        let after = staged
+            .iter()
+            .rposition(|(n, _)| dropped.contains(&(node.clone(), n.clone())))
+            .unwrap_or(staged.len() - 1);
+        staged.insert(after + 1, (node, Stage::Full));
+    }
+
+    staged
+}
+
+/// # Description
+/// Sorts packages such that every package comes after its dependencies
+///
+/// Unlike ``resolve()``, only the given packages are considered, and dependencies that aren't among
+/// them are ignored. This is used to order removals.
+///
+/// **Error conditions:**
+/// - the packages depend on each other in a cycle
+pub fn sort(packages: &[Package]) -> Result<Vec<Package>> {
+    let known: HashMap<String, &Package> =
+        packages.iter().map(|p| (p.relpath.clone(), p)).collect();
+    let roots = packages
+        .iter()
+        .map(|p| p.relpath.clone())
+        .collect::<Vec<_>>();
+
+    let order = toposort(&roots, |node| {
+        Ok(known.get(node).map_or_else(Vec::new, |p| {
+            p.dependencies
+                .iter()
+                .map(|d| qualify_quietly(d))
+                .filter(|d| known.contains_key(d))
+                .collect()
+        }))
+    })?;
+
+    Ok(order
+        .iter()
+        .filter_map(|node| known.get(node).map(|&p| p.clone()))
+        .collect())
+}
+
+/// # Description
+/// The reverse dependency index
+///
+/// Maps each dependency (as 'repo/name') to the installed packages that depend on it. Building it
+/// reads the LOCK of every installed package, so it's built once per run and passed around, and
+/// updated as packages are removed.
+#[derive(Debug, Default)]
+pub struct ReverseIndex(HashMap<String, Vec<Package>>);
+
+impl ReverseIndex {
+    /// # Description
+    /// Builds the reverse dependency index from the LOCKs of installed packages
+    #[instrument]
+    pub fn load() -> Self {
+        let mut index: HashMap<String, Vec<Package>> = HashMap::new();
+
+        for relpath in Set::new("//@i").unravel().iter() {
+            let Some((repo, name)) = relpath.split_once('/') else { continue };
+            let package = Package::new(repo, name);
+
+            for dep in &package.dependencies {
+                index
+                    .entry(qualify_quietly(dep))
+                    .or_default()
+                    .push(package.clone());
+            }
+        }
+
+        Self(index)
+    }
+
+    /// # Description
+    /// Returns the installed packages that depend on a package
+    pub fn rdeps(&self, package: &Package) -> &[Package] {
+        self.0.get(&package.relpath).map_or(&[], Vec::as_slice)
+    }
+
+    /// # Description
+    /// Returns true if a package was installed as a dependency and no installed package depends
+    /// on it
+    pub fn is_orphan(&self, package: &Package) -> bool {
+        !package.data.is_explicit && self.rdeps(package).is_empty()
+    }
+
+    /// # Description
+    /// Forgets a removed package, so it no longer depends on anything
+    pub fn forget(&mut self, package: &Package) {
+        self.0.retain(|_, dependents| {
+            dependents.retain(|d| d.relpath != package.relpath);
+            !dependents.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        ReverseIndex,
+        Stage,
+        resolve_with,
+        toposort,
+    };
+    use crate::utils::{
+        fail::Fail,
+        testing,
+    };
+
+    fn graph(edges: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        edges
+            .iter()
+            .map(|(n, deps)| {
+                (
+                    (*n).to_string(),
+                    deps.iter().map(ToString::to_string).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dependencies_come_first() {
+        let g = graph(&[
+            ("main/yajl", &["main/cmake"]),
+            ("main/cmake", &["main/curl", "main/libuv"]),
+            ("main/curl", &[]),
+            ("main/libuv", &[]),
+        ]);
+        let order = toposort(&["main/yajl".to_string()], |n| Ok(g[n].clone()))
+            .fail("Failed to sort a graph without cycles");
+        assert_eq!(order, [
+            "main/curl",
+            "main/libuv",
+            "main/cmake",
+            "main/yajl"
+        ]);
+    }
+
+    #[test]
+    fn shared_dependencies_appear_once() {
+        let g = graph(&[
+            ("main/a", &["main/c"]),
+            ("main/b", &["main/c"]),
+            ("main/c", &[]),
+        ]);
+        let roots = ["main/a".to_string(), "main/b".to_string()];
+        let order =
+            toposort(&roots, |n| Ok(g[n].clone())).fail("Failed to sort a graph without cycles");
+        assert_eq!(order, ["main/c", "main/a", "main/b"]);
+    }
+
+    #[test]
+    fn cycles_report_full_path() {
+        let g = graph(&[
+            ("main/freetype", &["main/harfbuzz"]),
+            ("main/harfbuzz", &["main/glib"]),
+            ("main/glib", &["main/freetype"]),
+        ]);
+        let result = toposort(&["main/freetype".to_string()], |n| Ok(g[n].clone()));
+        assert!(result.is_err_and(|e| {
+            e.to_string()
+                == "Circular dependency: main/freetype -> main/harfbuzz -> main/glib -> main/freetype"
+        }));
+    }
+
+    #[test]
+    fn bootstrapped_packages_are_rebuilt_after_their_dependencies() {
+        let package = |name: &str, deps: &[&str], bootstrap: &[&str]| {
+            let list = |l: &[&str]| {
+                l.iter()
+                    .map(|d| format!("\"main/{d}\""))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let extra = format!(
+                "dependencies = [{}]\nbootstrap = [{}]\n",
+                list(deps),
+                list(bootstrap)
+            );
+            testing::package(name, "1", &extra)
+        };
+        let repo = HashMap::from(
+            [
+                package("freetype", &["harfbuzz", "zlib"], &["harfbuzz"]),
+                package("harfbuzz", &["glib", "freetype"], &[]),
+                package("glib", &["zlib"], &[]),
+                package("zlib", &[], &[]),
+                package("cairo", &["freetype"], &[]),
+            ]
+            .map(|p| (p.relpath.clone(), p)),
+        );
+        let resolve = |roots: &[&str]| {
+            let packages = roots.iter().map(|r| repo[*r].clone()).collect::<Vec<_>>();
+            resolve_with(&packages, |n| repo[n].clone(), ToString::to_string)
+                .fail("Failed to resolve a breakable cycle")
+                .into_iter()
+                .map(|(p, s)| (p.name, s))
+                .collect::<Vec<_>>()
+        };
+        let staged = |s: &[(&str, Stage)]| {
+            s.iter()
+                .map(|(n, s)| ((*n).to_string(), *s))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            resolve(&["main/freetype"]),
+            staged(&[
+                ("zlib", Stage::Full),
+                ("glib", Stage::Full),
+                ("freetype", Stage::Bootstrap),
+                ("harfbuzz", Stage::Full),
+                ("freetype", Stage::Full),
+            ])
+        );
+
+        // dependents of a bootstrapped package come after its full stage
+        assert_eq!(
+            resolve(&["main/cairo"]),
+            staged(&[
+                ("zlib", Stage::Full),
+                ("glib", Stage::Full),
+                ("freetype", Stage::Bootstrap),
+                ("harfbuzz", Stage::Full),
+                ("freetype", Stage::Full),
+                ("cairo", Stage::Full),
+            ])
+        );
+    }
+
+    #[test]
+    fn removed_packages_are_forgotten() {
+        let package = |name: &str, explicit: bool| {
+            let mut package = testing::package(name, "1", "");
+            package.data.is_explicit = explicit;
+            package
+        };
+        let (zlib, glib, gtk) = (
+            package("zlib", false),
+            package("glib", false),
+            package("gtk", true),
+        );
+        let mut index = ReverseIndex(HashMap::from([
+            ("main/zlib".to_string(), vec![glib.clone(), gtk.clone()]),
+            ("main/glib".to_string(), vec![gtk.clone()]),
+        ]));
+
+        assert_eq!(index.rdeps(&zlib).len(), 2);
+        assert!(!index.is_orphan(&glib));
+        assert!(!index.is_orphan(&gtk));
+
+        index.forget(&gtk);
+        assert!(index.is_orphan(&glib));
+        assert!(!index.is_orphan(&zlib));
+
+        index.forget(&glib);
+        assert!(index.rdeps(&zlib).is_empty());
+        assert!(index.is_orphan(&zlib));
+    }
+}
@@ -9,6 +9,7 @@ use std::{
     },
 };
 
+use anyhow::Result;
 use tracing::debug;
 
 use super::Package;
@@ -21,6 +22,7 @@ use crate::{
         BoolFail,
         Fail,
     },
+    utils::json,
 };
 
 impl Package {
@@ -58,6 +60,9 @@ impl Package {
             .unwrap_or_default()
             .trim()
             .to_string();
+        // packages installed before explicit markers existed are assumed to be explicit
+        package.data.is_explicit = fs::read_to_string(port_dir.join(".data").join("EXPLICIT"))
+            .map_or(true, |s| s.trim() != "false");
         package.data.dist = port_dir.join(".dist").join(&dist_tb);
         package.data.port_dir = port_dir;
         package.status();
@@ -66,6 +71,16 @@ impl Package {
         package
     }
 
+    /// # Description
+    /// Records whether a package was installed explicitly or as a dependency
+    ///
+    /// The marker is stored in ``$PORT/.data/EXPLICIT``
+    pub fn mark_explicit(&self, explicit: bool) -> Result<()> {
+        let marker = self.data.port_dir.join(".data").join("EXPLICIT");
+        fs::write(marker, format!("{explicit}\n"))?;
+        Ok(())
+    }
+
     pub fn is_outdated(&self) -> bool {
         self.data.is_installed && self.data.installed_version != self.version
     }
@@ -90,6 +105,10 @@ impl Package {
     }
 
     pub fn about(&self) {
+        if json::enabled() {
+            return json::emit(&self.summary());
+        }
+
         let status = &self.data.status;
         let sty = if status.contains("Available") {
             "\x1b[30m"
@@ -111,6 +130,10 @@ impl Package {
     }
 
     pub fn long_about(&self) {
+        if json::enabled() {
+            return json::emit(&self.summary());
+        }
+
         let status = &self.data.status;
         let sty = if status.contains("Available") {
             "\x1b[30m"
@@ -141,6 +164,14 @@ impl Package {
             .map_or("No categories".to_owned(), |c| c.join(", "));
 
         pr!("\x1b[37m󰓻 {categories}");
+
+        let dependencies = if self.dependencies.is_empty() {
+            "No dependencies".to_owned()
+        } else {
+            self.dependencies.join(", ")
+        };
+
+        pr!("\x1b[37m {dependencies}");
         // pr!("\x1b[37m {}") // license
     }
 }
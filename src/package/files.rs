@@ -0,0 +1,218 @@
+// src/package/files.rs
+//! Lists the files owned by installed packages
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use tracing::instrument;
+
+use super::Package;
+use crate::{
+    remove::manifest::{
+        self,
+        Kind,
+        Record,
+    },
+    utils::comms::{
+        erm,
+        msg,
+        pr,
+    },
+};
+
+/// # Description
+/// Restricts which files are listed
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Only {
+    /// Executables in a bin or sbin directory
+    Bins,
+    /// Shared and static libraries
+    Libs,
+    /// Everything but directories
+    Files,
+}
+
+impl Only {
+    /// # Description
+    /// Returns true if a record passes the filter
+    fn keeps(self, record: &Record) -> bool {
+        let mut components = record.path.rsplit('/');
+        let name = components.next().unwrap_or_default();
+        let parent = components.next().unwrap_or_default();
+
+        match self {
+            | Self::Bins => record.kind != Kind::Dir && matches!(parent, "bin" | "sbin"),
+            | Self::Libs => {
+                let extension = Path::new(name).extension().unwrap_or_default();
+                record.kind != Kind::Dir
+                    && (extension == "so" || extension == "a" || name.contains(".so."))
+            },
+            | Self::Files => record.kind != Kind::Dir,
+        }
+    }
+}
+
+/// # Description
+/// Formats a record for display, showing where symlinks point
+fn describe(name: &str, record: &Record) -> String {
+    match &record.target {
+        | Some(target) => format!("{name} -> {target}"),
+        | None => name.to_string(),
+    }
+}
+
+/// # Description
+/// A node in a file tree
+#[derive(Default)]
+struct Node<'a> {
+    record:   Option<&'a Record>,
+    children: BTreeMap<&'a str, Self>,
+}
+
+/// # Description
+/// Renders records as a tree, like ``tree``
+///
+/// Directories containing records are shown even if they aren't records themselves
+fn render_tree(records: &[Record]) -> Vec<String> {
+    fn render(node: &Node, prefix: &str, lines: &mut Vec<String>) {
+        let count = node.children.len();
+        for (i, (name, child)) in node.children.iter().enumerate() {
+            let last = i + 1 == count;
+            let branch = if last { "└── " } else { "├── " };
+            let line = child
+                .record
+                .map_or_else(|| name.to_string(), |r| describe(name, r));
+            lines.push(format!("{prefix}{branch}{line}"));
+
+            let indent = if last { "    " } else { "│   " };
+            render(child, &format!("{prefix}{indent}"), lines);
+        }
+    }
+
+    let mut root = Node::default();
+    for record in records {
+        let mut node = &mut root;
+        for component in record.path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children.entry(component).or_default();
+        }
+        node.record = Some(record);
+    }
+
+    let mut lines = vec!["/".to_string()];
+    render(&root, "", &mut lines);
+    lines
+}
+
+/// # Description
+/// Reads the records for a package's installed version, filtered and sorted by path
+///
+/// **Error conditions:**
+/// - the manifest couldn't be read
+fn read(package: &Package, only: Option<Only>) -> Result<Vec<Record>> {
+    let manifest = package
+        .data
+        .port_dir
+        .join(".data")
+        .join(format!("MANIFEST={}", package.data.installed_version));
+
+    let mut records = manifest::read(&manifest)?;
+    records.retain(|r| only.is_none_or(|o| o.keeps(r)));
+    records.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(records)
+}
+
+/// # Description
+/// Lists the files an installed package owns, according to its manifest
+///
+/// Files may be restricted with ``only``, and displayed as a tree
+#[instrument(skip(package))]
+pub fn list(package: &Package, only: Option<Only>, tree: bool) {
+    if !package.data.is_installed {
+        return erm!("Not installed: '{package}'");
+    }
+
+    let records = match read(package, only) {
+        | Ok(r) => r,
+        | Err(e) => return erm!("Failed to read the manifest for '{package}': {e:#}"),
+    };
+
+    if records.is_empty() {
+        return msg!("No matching files for '{package}'");
+    }
+
+    msg!("Files owned by '{package}':");
+    let lines = if tree {
+        render_tree(&records)
+    } else {
+        records.iter().map(|r| describe(&r.path, r)).collect()
+    };
+
+    for line in &lines {
+        pr!("{line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Only,
+        render_tree,
+    };
+    use crate::remove::manifest::{
+        Kind,
+        Record,
+    };
+
+    fn record(path: &str, kind: Kind) -> Record {
+        let mut record = Record::legacy(path);
+        record.kind = kind;
+        if kind == Kind::Symlink {
+            record.target = Some("libz.so.1".to_string());
+        }
+        record
+    }
+
+    #[test]
+    fn filters_and_renders_trees() {
+        let records = [
+            record("/usr/bin", Kind::Dir),
+            record("/usr/bin/minigzip", Kind::File),
+            record("/usr/lib/libz.a", Kind::File),
+            record("/usr/lib/libz.so", Kind::Symlink),
+            record("/usr/lib/libz.so.1", Kind::File),
+            record("/usr/lib/pkgconfig/zlib.pc", Kind::File),
+        ];
+
+        let kept = |only: Only| {
+            records
+                .iter()
+                .filter(|r| only.keeps(r))
+                .map(|r| r.path.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kept(Only::Bins), ["/usr/bin/minigzip"]);
+        assert_eq!(kept(Only::Libs), [
+            "/usr/lib/libz.a",
+            "/usr/lib/libz.so",
+            "/usr/lib/libz.so.1"
+        ]);
+        assert_eq!(kept(Only::Files).len(), 5);
+
+        assert_eq!(render_tree(&records), [
+            "/",
+            "└── usr",
+            "    ├── bin",
+            "    │   └── minigzip",
+            "    └── lib",
+            "        ├── libz.a",
+            "        ├── libz.so -> libz.so.1",
+            "        ├── libz.so.1",
+            "        └── pkgconfig",
+            "            └── zlib.pc",
+        ]);
+    }
+}
@@ -2,14 +2,20 @@
 //! Defines the package type
 
 pub mod ambiguity;
+pub mod configs;
+pub mod dependencies;
 pub mod endpoints;
+pub mod files;
 pub mod history;
 pub mod parse;
 pub mod provides;
 pub mod repos;
+pub mod search;
 pub mod sets;
 pub mod stats;
+pub mod summary;
 pub mod traits;
+pub mod verify;
 
 use std::{
     path::PathBuf,
@@ -33,6 +39,17 @@ pub struct Package {
     pub categories:  Option<Vec<String>>,
     pub description: Option<String>,
 
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    #[serde(default)]
+    pub bootstrap:    Vec<String>,
+    #[serde(default)]
+    pub conflicts:    Vec<String>,
+
+    pub isolate: Option<bool>,
+    #[serde(default)]
+    pub network: bool,
+
     #[serde(default)]
     pub extra:   Arc<[PackageSource]>,
     #[serde(default)]
@@ -63,12 +80,18 @@ pub struct PackageData {
     pub is_installed:      bool,
     #[serde(skip)]
     pub installed_version: String,
+    #[serde(skip)]
+    pub is_explicit:       bool,
 }
 
 /// # Description
 /// The package source struct
+///
+/// Fallbacks are other urls for the same file, tried in order if ``url`` fails
 #[derive(Deserialize, Debug, Default, Clone)]
 pub struct PackageSource {
-    pub url:  Arc<str>, // must be thread safe
-    pub hash: String,
+    pub url:       Arc<str>, // must be thread safe
+    #[serde(default)]
+    pub fallbacks: Vec<Arc<str>>,
+    pub hash:      String,
 }
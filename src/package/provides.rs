@@ -1,98 +1,209 @@
 // src/package/provides.rs
 //! Utilities for seeing what package(s) provide a specific path
+//!
+//! Queries are matched against the paths installed packages own:
+//! - absolute paths match exactly (``/usr/bin/grep``)
+//! - names without a slash match basenames (``libz.so``)
+//! - globs match full paths, or basenames if they contain no slash (``/usr/bin/*grep``,
+//!   ``libz.so*``)
+//! - regexes, if requested, search full paths (``^/usr/lib/libz\.so\.[0-9]+$``)
 
-use std::{
-    fs,
-    path::{
-        Path,
-        PathBuf,
-    },
-};
+use std::path::Path;
 
-use anyhow::Context;
+use anyhow::{
+    Context,
+    Result,
+};
+use glob::{
+    MatchOptions,
+    Pattern,
+};
+use regex::Regex;
+use serde::Serialize;
 use tracing::instrument;
 
-use super::Package;
 use crate::{
-    pm::PM,
-    remove::manifest,
+    db::Db,
+    globals::config::CONFIG,
+    remove::manifest::Kind,
     utils::{
-        comms::erm,
+        comms::{
+            erm,
+            msg,
+            pr,
+        },
         fail::Fail,
+        hash::try_truncate_commit_hash,
+        json,
     },
 };
 
 /// # Description
-/// Finds all manifests containing a given path
-#[instrument]
-fn find_manifests_with_path(path: &str) -> Vec<PathBuf> {
-    manifest::locate("/var/ports")
-        .iter()
-        .filter_map(|m| {
-            let contents = fs::read_to_string(m).fail("Failed to read manifest");
-            let lines = contents.lines();
-            for line in lines {
-                if line == path {
-                    return Some(m.clone());
-                }
+/// How a query is matched against installed paths
+#[derive(Debug)]
+enum Query {
+    Exact(String),
+    Basename(String),
+    Glob {
+        pattern:  Pattern,
+        basename: bool,
+    },
+    Regex(Regex),
+}
+
+impl Query {
+    /// # Description
+    /// Interprets a query
+    ///
+    /// **Error conditions:**
+    /// - the query is an invalid glob or regex
+    fn new(query: &str, regex: bool) -> Result<Self> {
+        if regex {
+            return Ok(Self::Regex(
+                Regex::new(query).with_context(|| format!("Invalid regex '{query}'"))?,
+            ));
+        }
+
+        let basename = !query.contains('/');
+        Ok(if query.contains(['*', '?', '[']) {
+            Self::Glob {
+                pattern: Pattern::new(query).with_context(|| format!("Invalid glob '{query}'"))?,
+                basename,
             }
-            None
+        } else if basename {
+            Self::Basename(query.to_string())
+        } else {
+            Self::Exact(query.to_string())
         })
-        .collect::<Vec<_>>()
+    }
+
+    /// # Description
+    /// Returns true if an installed path matches the query
+    fn matches(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+
+        match self {
+            | Self::Exact(q) => path == q,
+            | Self::Basename(q) => name == q,
+            | Self::Glob { pattern, basename } => {
+                pattern.matches_with(if *basename { name } else { path }, options)
+            },
+            | Self::Regex(r) => r.is_match(path),
+        }
+    }
 }
 
 /// # Description
-/// Returns a package given its manifest
-#[instrument]
-fn get_package_from_manifest(manifest: &Path) -> Package {
-    let components = manifest.components().collect::<Vec<_>>();
-
-    let repo = components
-        .get(3)
-        .context("Are you using /var/ports?")
-        .fail("Failed to parse package repo from manifest path")
-        .as_os_str()
-        .to_string_lossy();
-    let name = components
-        .get(4)
-        .context("Are you using /var/ports?")
-        .fail("Failed to parse package name from manifest path")
-        .as_os_str()
-        .to_string_lossy();
-
-    Package::new(&repo, &name)
+/// An installed path matching a query, and the package that owns it
+#[derive(Serialize, Debug)]
+struct Match<'d> {
+    package: &'d str,
+    version: &'d str,
+    path:    &'d str,
+}
+
+/// # Description
+/// Finds the installed paths matching a query, along with the packages that own them
+///
+/// Matches are sorted by package. Directories only match exact queries, since they're shared by
+/// many packages (see ``is_dir()``).
+#[instrument(skip(db))]
+fn find_matches<'d>(db: &'d Db, query: &Query) -> Vec<Match<'d>> {
+    db.packages()
+        .flat_map(|(relpath, installed)| {
+            installed
+                .paths
+                .iter()
+                .filter(|(kind, path)| {
+                    query.matches(path)
+                        && (matches!(query, Query::Exact(_)) || !is_dir(*kind, path))
+                })
+                .map(move |(_, path)| Match {
+                    package: relpath,
+                    version: &installed.version,
+                    path,
+                })
+        })
+        .collect()
+}
+
+/// # Description
+/// Returns true if an installed path is a directory
+///
+/// Old manifests don't record types, so paths of unknown type are checked on disk
+fn is_dir(kind: Kind, path: &str) -> bool {
+    match kind {
+        | Kind::Dir => true,
+        | Kind::Unknown => Path::new(&CONFIG.general.prefix)
+            .join(path.trim_start_matches('/'))
+            .is_dir(),
+        | _ => false,
+    }
 }
 
 /// # Description
-/// Lists the packages that provide a given path
+/// Lists the packages that provide paths matching a query
+///
+/// Each matching path is displayed next to the package that owns it. If ``regex`` is true, the
+/// query is a regex. Otherwise, see the module documentation.
 ///
 /// Handles display, returns nothing
 #[instrument]
-pub fn provides(path: &str) {
-    let manifests = find_manifests_with_path(path);
-
-    let total = manifests.len();
-    match total {
-        | 0 => erm!("No installed packages provide '{path}'"),
-        | 1 => {
-            let package = get_package_from_manifest(manifests.first().fail("1 != 1"));
-            let packages = [package; 1];
-            PM::list_packages(
-                &packages,
-                &format!("1 installed package provides '{path}'"),
-                false,
-            );
-        },
-        | _ => {
-            let packages = manifests
-                .iter()
-                .map(|m| get_package_from_manifest(m))
-                .collect::<Vec<_>>();
-            PM::list_packages(
-                &packages,
-                &format!("{total} installed packages provide '{path}'"),
-                false,
-            );
-        },
+pub fn provides(query: &str, regex: bool) {
+    let matcher = Query::new(query, regex).fail("Invalid query");
+    let db = Db::load().fail("Failed to load the database");
+    let matches = find_matches(&db, &matcher);
+
+    if matches.is_empty() {
+        return erm!("No installed packages provide '{query}'");
+    }
+
+    if json::enabled() {
+        return matches.iter().for_each(json::emit);
+    }
+
+    let mut packages = matches.iter().map(|m| m.package).collect::<Vec<_>>();
+    packages.dedup();
+    match packages.len() {
+        | 1 => msg!("1 installed package provides '{query}':"),
+        | n => msg!("{n} installed packages provide '{query}':"),
+    }
+
+    let max_width: usize = 48;
+    for m in &matches {
+        let version = try_truncate_commit_hash(m.version);
+        let package_info = format!("  \x1b[0;37m{}={version}", m.package);
+        let width = max_width.saturating_sub(package_info.len());
+        pr!("{package_info} {:<width$} ~ {}", " ", m.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Query;
+    use crate::utils::fail::Fail;
+
+    #[test]
+    fn queries_match_paths_basenames_globs_and_regexes() {
+        let query = |q: &str, regex| Query::new(q, regex).fail("Invalid query");
+
+        assert!(query("/usr/bin/grep", false).matches("/usr/bin/grep"));
+        assert!(!query("/usr/bin/grep", false).matches("/usr/bin/egrep"));
+
+        assert!(query("libz.so", false).matches("/usr/lib/libz.so"));
+        assert!(!query("libz.so", false).matches("/usr/lib/libz.so.1"));
+
+        assert!(query("/usr/bin/*grep", false).matches("/usr/bin/egrep"));
+        assert!(!query("/usr/*grep", false).matches("/usr/bin/egrep"));
+        assert!(query("libz.so*", false).matches("/usr/lib/libz.so.1.3"));
+
+        assert!(query(r"^/usr/lib/libz\.so\.[0-9]+$", true).matches("/usr/lib/libz.so.1"));
+        assert!(!query(r"^/usr/lib/libz\.so\.[0-9]+$", true).matches("/usr/lib/libz.so.1.3"));
+
+        assert!(Query::new("[", false).is_err());
     }
 }
@@ -0,0 +1,183 @@
+// src/package/search.rs
+//! Searches packages across all repos
+
+use regex::{
+    Regex,
+    RegexBuilder,
+};
+use serde::Serialize;
+use tracing::instrument;
+
+use super::{
+    Package,
+    parse::expand_set,
+    summary::Summary,
+};
+use crate::{
+    globals::config::CONFIG,
+    pm::PM,
+    utils::{
+        comms::erm,
+        fail::Fail,
+        json,
+    },
+};
+
+/// # Description
+/// How well a package matches a search, best first
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+enum Rank {
+    /// The whole name matches
+    Exact,
+    /// Part of the name matches
+    Name,
+    /// The description, categories, or upstream match
+    Other,
+}
+
+impl Rank {
+    const fn heading(self) -> &'static str {
+        match self {
+            | Self::Exact => "Exact matches",
+            | Self::Name => "Name matches",
+            | Self::Other => "Other matches",
+        }
+    }
+}
+
+/// # Description
+/// A package matching a search, as displayed by ``--json``
+#[derive(Serialize, Debug)]
+struct SearchMatch<'p> {
+    #[serde(rename = "match")]
+    rank:    Rank,
+    #[serde(flatten)]
+    package: Summary<'p>,
+}
+
+/// # Description
+/// Ranks a package against a search, returning None if it doesn't match
+fn rank(package: &Package, re: &Regex) -> Option<Rank> {
+    if re
+        .find(&package.name)
+        .is_some_and(|m| m.len() == package.name.len())
+    {
+        return Some(Rank::Exact);
+    }
+
+    if re.is_match(&package.name) {
+        return Some(Rank::Name);
+    }
+
+    let description = package.description.as_deref().unwrap_or_default();
+    let categories = package.categories.as_deref().unwrap_or_default();
+    #[cfg(feature = "upstream")]
+    let upstream = package.upstream.as_deref().unwrap_or_default();
+    #[cfg(not(feature = "upstream"))]
+    let upstream = "";
+
+    (re.is_match(description) || categories.iter().any(|c| re.is_match(c)) || re.is_match(upstream))
+        .then_some(Rank::Other)
+}
+
+/// # Description
+/// Searches every package in every repo
+///
+/// The pattern is a case-insensitive regex matched against names, descriptions, categories, and
+/// upstreams. Matches are listed with exact name matches first, then other name matches, then
+/// everything else. With ``--json``, each match records how it matched (``exact``, ``name``, or
+/// ``other``).
+#[instrument]
+pub fn search(pattern: &str) {
+    let re = RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .efail(|| format!("Invalid search '{pattern}'"));
+
+    let mut ranked = expand_set("//@@")
+        .iter()
+        .filter_map(|p| rank(p, &re).map(|r| (r, p.clone())))
+        .collect::<Vec<_>>();
+
+    if ranked.is_empty() {
+        return erm!("No packages match '{pattern}'");
+    }
+
+    if json::enabled() {
+        // ordered as they'd be listed
+        ranked.sort_by_cached_key(|(r, p)| {
+            (
+                *r,
+                CONFIG
+                    .general
+                    .alphabetize
+                    .then(|| format!("{}/{p}", p.repo)),
+            )
+        });
+        return ranked.iter().for_each(|(rank, p)| {
+            json::emit(&SearchMatch {
+                rank:    *rank,
+                package: p.summary(),
+            });
+        });
+    }
+
+    for rank in [Rank::Exact, Rank::Name, Rank::Other] {
+        let packages = ranked
+            .iter()
+            .filter(|(r, _)| *r == rank)
+            .map(|(_, p)| p.clone())
+            .collect::<Vec<_>>();
+
+        if !packages.is_empty() {
+            PM::list_packages(
+                &packages,
+                &format!("{} for '{pattern}'", rank.heading()),
+                false,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::RegexBuilder;
+
+    use super::{
+        Rank,
+        rank,
+    };
+    use crate::utils::{
+        fail::Fail,
+        testing,
+    };
+
+    #[test]
+    fn ranks_exact_names_first() {
+        let package = |name: &str, description: &str| {
+            let extra = format!("description = \"{description}\"\ncategories = [\"core\"]\n");
+            testing::package(name, "1", &extra)
+        };
+        let re = |p: &str| {
+            RegexBuilder::new(p)
+                .case_insensitive(true)
+                .build()
+                .fail("Invalid regex")
+        };
+
+        let grep = package("grep", "Searches files for lines matching a pattern");
+        let ripgrep = package("ripgrep", "Recursive line-oriented search tool");
+        let tree = package("tree", "Lists directories as trees");
+
+        assert_eq!(rank(&grep, &re("GREP")), Some(Rank::Exact));
+        assert_eq!(rank(&ripgrep, &re("grep")), Some(Rank::Name));
+        assert_eq!(rank(&ripgrep, &re("^grep$")), None);
+        assert_eq!(rank(&tree, &re("director")), Some(Rank::Other));
+        assert_eq!(rank(&tree, &re("^core$")), Some(Rank::Other));
+        assert_eq!(rank(&tree, &re("search")), None);
+
+        let json = serde_json::to_value(Rank::Name).fail("Failed to serialize");
+        assert_eq!(json, "name");
+    }
+}
@@ -15,7 +15,6 @@ use std::{
         BufRead,
         BufReader,
     },
-    path::Path,
     rc::Rc,
 };
 
@@ -27,17 +26,21 @@ use tracing::{
 
 use super::{
     ambiguity::resolve_set_ambiguity,
+    dependencies::ReverseIndex,
     repos,
 };
-use crate::utils::{
-    comms::{
-        erm,
-        pr,
-        vpr,
-    },
-    fail::{
-        BoolFail,
-        Fail,
+use crate::{
+    db::Db,
+    utils::{
+        comms::{
+            erm,
+            pr,
+            vpr,
+        },
+        fail::{
+            BoolFail,
+            Fail,
+        },
     },
 };
 
@@ -105,17 +108,19 @@ impl Set {
 
     /// # Description
     /// Returns true if a given string is a special set
-    fn is_special(&self) -> bool {
-        matches!(
-            self.set.as_str(),
-            "@@" | "@all" | "@o" | "@outdated" | "@i" | "@installed" | "@a" | "@available"
-        )
-    }
+    fn is_special(&self) -> bool { Self::is_special_set(&self.set) }
 
     pub fn is_special_set(set: &str) -> bool {
         matches!(
             set,
-            "@@" | "@all" | "@o" | "@outdated" | "@i" | "@installed" | "@a" | "@available"
+            "@@" | "@all"
+                | "@o"
+                | "@outdated"
+                | "@i"
+                | "@installed"
+                | "@a"
+                | "@available"
+                | "@orphans"
         )
     }
 
@@ -129,6 +134,8 @@ impl Set {
             self.available()
         } else if matches!(set, "@@" | "@all") {
             self.all()
+        } else if set == "@orphans" {
+            self.orphans()
         } else {
             unreachable!("I forgot to add a special set")
         }
@@ -219,9 +226,10 @@ impl Set {
     ///
     /// alias: @i
     fn installed(&self) -> Rc<[String]> {
+        let db = Db::load().fail("Failed to load the database");
         self.all()
             .iter()
-            .filter(|p| Path::new(&format!("/var/ports/{p}/.data/INSTALLED")).exists())
+            .filter(|p| db.is_installed(p))
             .cloned()
             .collect::<Vec<_>>()
             .into()
@@ -232,9 +240,28 @@ impl Set {
     ///
     /// alias: @a
     fn available(&self) -> Rc<[String]> {
+        let db = Db::load().fail("Failed to load the database");
         self.all()
             .iter()
-            .filter(|p| !Path::new(&format!("/var/ports/{p}/.data/INSTALLED")).exists())
+            .filter(|p| !db.is_installed(p))
+            .cloned()
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    /// # Description
+    /// Unravels the special set '@orphans', which contains every installed package in a repo that
+    /// was installed as a dependency and that no installed package depends on anymore
+    fn orphans(&self) -> Rc<[String]> {
+        let index = ReverseIndex::load();
+        self.installed()
+            .iter()
+            .filter(|p| {
+                let (repo, name) = p
+                    .split_once('/')
+                    .efail(|| format!("[UNREACHABLE] Misformatted package '{p}'"));
+                index.is_orphan(&super::Package::new(repo, name))
+            })
             .cloned()
             .collect::<Vec<_>>()
             .into()
@@ -24,6 +24,7 @@ use crate::utils::{
         pr,
     },
     fail::Fail,
+    json,
     time::Pretty,
 };
 
@@ -62,11 +63,21 @@ impl PackageStats {
         self.build_times.push(time);
     }
 
+    /// # Description
+    /// Returns the average build time, or None if the package hasn't been built
+    pub fn average_build_time(&self) -> Option<Duration> {
+        (!self.build_times.is_empty()).then(|| from_micros_f64(avg(&self.build_times)))
+    }
+
     pub fn display(&self, package: &Package) {
         if !package.data.port_dir.join(".data").join("STATS").exists() {
             return erm!("No stats exist for '{package}'");
         }
 
+        if json::enabled() {
+            return json::emit(&self.summary(package));
+        }
+
         msg!("Stats for {package}:");
         {
             // TODO: Add last_built timestamp
@@ -78,6 +89,30 @@ impl PackageStats {
     }
 }
 
+/// # Description
+/// A package's stats, as displayed by ``--json``
+#[derive(Serialize, Debug)]
+pub struct StatsSummary<'p> {
+    pub package:            &'p str,
+    pub timestamp:          &'p str,
+    /// The average build time in seconds
+    pub average_build_time: f64,
+    pub builds:             usize,
+}
+
+impl PackageStats {
+    /// # Description
+    /// Summarizes a package's stats for ``--json``
+    pub fn summary<'p>(&self, package: &'p Package) -> StatsSummary<'p> {
+        StatsSummary {
+            package:            &package.relpath,
+            timestamp:          &package.timestamp,
+            average_build_time: from_micros_f64(avg(&self.build_times)).as_secs_f64(),
+            builds:             self.build_times.len(),
+        }
+    }
+}
+
 #[allow(clippy::cast_precision_loss)]
 fn avg(pts: &[u64]) -> f64 {
     if pts.is_empty() {
@@ -0,0 +1,96 @@
+// src/package/summary.rs
+//! Summarizes packages for machine-readable output
+
+use serde::Serialize;
+
+use super::Package;
+
+/// # Description
+/// Whether a package is installed, and if so, whether it's up to date
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Available,
+    Outdated,
+    Installed,
+}
+
+/// # Description
+/// A package's information, as displayed by ``--json``
+#[derive(Serialize, Debug)]
+pub struct Summary<'p> {
+    pub repo:              &'p str,
+    pub name:              &'p str,
+    pub version:           &'p str,
+    pub installed_version: Option<&'p str>,
+    pub status:            Status,
+    pub description:       Option<&'p str>,
+    pub categories:        &'p [String],
+    pub dependencies:      &'p [String],
+    #[cfg(feature = "upstream")]
+    pub upstream:          Option<&'p str>,
+}
+
+impl Package {
+    /// # Description
+    /// Returns a package's status
+    pub fn status_kind(&self) -> Status {
+        if !self.data.is_installed {
+            Status::Available
+        } else if self.is_outdated() {
+            Status::Outdated
+        } else {
+            Status::Installed
+        }
+    }
+
+    /// # Description
+    /// Summarizes a package for ``--json``
+    pub fn summary(&self) -> Summary<'_> {
+        Summary {
+            repo: &self.repo,
+            name: &self.name,
+            version: &self.version,
+            installed_version: self
+                .data
+                .is_installed
+                .then_some(self.data.installed_version.as_str()),
+            status: self.status_kind(),
+            description: self.description.as_deref(),
+            categories: self.categories.as_deref().unwrap_or_default(),
+            dependencies: &self.dependencies,
+            #[cfg(feature = "upstream")]
+            upstream: self.upstream.as_deref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::{
+        fail::Fail,
+        testing,
+    };
+
+    #[test]
+    fn summarizes_packages_as_json() {
+        let mut package = testing::package(
+            "tree",
+            "2.2.1",
+            "categories = [\"utils\"]\ndependencies = [\"main/glibc\"]\n",
+        );
+
+        let json = serde_json::to_value(package.summary()).fail("Failed to serialize");
+        assert_eq!(json["repo"], "main");
+        assert_eq!(json["status"], "available");
+        assert!(json["installed_version"].is_null());
+        assert_eq!(json["categories"][0], "utils");
+        assert_eq!(json["dependencies"][0], "main/glibc");
+
+        package.data.is_installed = true;
+        package.data.installed_version = "2.1.0".to_string();
+        let json = serde_json::to_value(package.summary()).fail("Failed to serialize");
+        assert_eq!(json["status"], "outdated");
+        assert_eq!(json["installed_version"], "2.1.0");
+    }
+}
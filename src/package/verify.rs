@@ -0,0 +1,212 @@
+// src/package/verify.rs
+//! Verifies installed files against package manifests
+
+use std::{
+    fmt::{
+        self,
+        Display,
+        Formatter,
+    },
+    path::Path,
+};
+
+use anyhow::Result;
+use tracing::instrument;
+
+use super::Package;
+use crate::{
+    globals::config::CONFIG,
+    remove::manifest::{
+        self,
+        Kind,
+        Record,
+    },
+};
+
+/// # Description
+/// A way an installed path differs from what was installed
+#[derive(Debug, PartialEq, Eq)]
+pub enum Problem {
+    Missing,
+    Type {
+        expected: Kind,
+        found:    Kind,
+    },
+    Mode {
+        expected: u32,
+        found:    u32,
+    },
+    Owner {
+        expected: (u32, u32),
+        found:    (u32, u32),
+    },
+    Content,
+    Target {
+        expected: String,
+        found:    String,
+    },
+    Unreadable(String),
+}
+
+impl Problem {
+    /// # Description
+    /// Returns true if the path is missing, rather than modified
+    pub const fn is_missing(&self) -> bool { matches!(self, Self::Missing) }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            | Self::Missing => write!(f, "missing"),
+            | Self::Type { expected, found } => write!(f, "{expected} is now a {found}"),
+            | Self::Mode { expected, found } => write!(f, "mode {expected:04o} -> {found:04o}"),
+            | Self::Owner { expected, found } => write!(
+                f,
+                "owner {}:{} -> {}:{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            | Self::Content => write!(f, "contents modified"),
+            | Self::Target { expected, found } => write!(f, "target '{expected}' -> '{found}'"),
+            | Self::Unreadable(e) => write!(f, "unreadable ({e})"),
+        }
+    }
+}
+
+/// # Description
+/// Checks an installed path against its record
+///
+/// Paths from old manifests can only be checked for existence. Directories are only checked for
+/// existence, since they may be shared with, or predate, the package.
+fn check(record: &Record, prefix: &Path) -> Option<Problem> {
+    let path = prefix.join(record.path.trim_start_matches('/'));
+
+    // compare against what's actually there, rather than what it might point to
+    let Ok(found) = Record::new(&record.path, &path) else {
+        return if path.symlink_metadata().is_ok() {
+            Some(Problem::Unreadable(format!(
+                "failed to read '{}'",
+                path.display()
+            )))
+        } else {
+            Some(Problem::Missing)
+        };
+    };
+
+    match record.kind {
+        | Kind::Unknown => return None,
+        // directories may be replaced with symlinks to directories
+        | Kind::Dir if path.is_dir() => return None,
+        | expected if expected != found.kind => {
+            return Some(Problem::Type { expected, found: found.kind });
+        },
+        | _ => {},
+    }
+
+    if record.kind == Kind::Symlink {
+        return (record.target != found.target).then(|| Problem::Target {
+            expected: record.target.clone().unwrap_or_default(),
+            found:    found.target.unwrap_or_default(),
+        });
+    }
+
+    if record.size != found.size || record.hash != found.hash {
+        return Some(Problem::Content);
+    }
+
+    if record.mode != found.mode {
+        return Some(Problem::Mode {
+            expected: record.mode,
+            found:    found.mode,
+        });
+    }
+
+    if (record.uid, record.gid) != (found.uid, found.gid) {
+        return Some(Problem::Owner {
+            expected: (record.uid, record.gid),
+            found:    (found.uid, found.gid),
+        });
+    }
+
+    None
+}
+
+/// # Description
+/// Verifies every path in an installed package's manifest
+///
+/// Returns the paths that differ from what was installed, along with how
+///
+/// **Error conditions:**
+/// - the manifest for the installed version couldn't be read
+#[instrument(skip(package))]
+pub fn verify(package: &Package) -> Result<Vec<(String, Problem)>> {
+    let manifest = package
+        .data
+        .port_dir
+        .join(".data")
+        .join(format!("MANIFEST={}", package.data.installed_version));
+    let records = manifest::read(&manifest)?;
+    let prefix = Path::new(&CONFIG.general.prefix);
+
+    Ok(records
+        .iter()
+        .filter_map(|r| check(r, prefix).map(|p| (r.path.clone(), p)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::symlink,
+    };
+
+    use super::{
+        Problem,
+        check,
+    };
+    use crate::{
+        remove::manifest::{
+            Kind,
+            Record,
+        },
+        utils::{
+            fail::Fail,
+            testing::Scratch,
+        },
+    };
+
+    #[test]
+    fn detects_modified_and_missing_files() {
+        let prefix = Scratch::new("verify");
+        fs::create_dir_all(prefix.join("usr/bin")).fail("Failed to create prefix");
+        fs::write(prefix.join("usr/bin/tree"), "tree").fail("Failed to write file");
+        symlink("tree", prefix.join("usr/bin/t")).fail("Failed to create symlink");
+
+        let record = |p: &str| Record::new(p, &prefix.join(&p[1..])).fail("Failed to record");
+        let dir = record("/usr/bin");
+        let file = record("/usr/bin/tree");
+        let link = record("/usr/bin/t");
+
+        assert_eq!(check(&dir, &prefix), None);
+        assert_eq!(check(&file, &prefix), None);
+        assert_eq!(check(&link, &prefix), None);
+
+        fs::write(prefix.join("usr/bin/tree"), "modified").fail("Failed to write file");
+        assert_eq!(check(&file, &prefix), Some(Problem::Content));
+
+        fs::remove_file(prefix.join("usr/bin/t")).fail("Failed to remove symlink");
+        fs::create_dir(prefix.join("usr/bin/t")).fail("Failed to create directory");
+        assert_eq!(
+            check(&link, &prefix),
+            Some(Problem::Type {
+                expected: Kind::Symlink,
+                found:    Kind::Dir,
+            })
+        );
+
+        assert_eq!(
+            check(&Record::legacy("/usr/bin/gone"), &prefix),
+            Some(Problem::Missing)
+        );
+    }
+}
@@ -1,6 +1,8 @@
 // src/pm/endpoints.rs
 //! Defines endpoints for PM
 
+use std::collections::HashSet;
+
 use indicatif::ProgressStyle;
 use once_cell::sync::Lazy;
 #[cfg(feature = "parallelism")]
@@ -11,25 +13,50 @@ use tracing::{
     warn,
 };
 
-use super::PM;
+use super::{
+    PM,
+    plan::Plan,
+};
 #[cfg(feature = "upstream")]
 use crate::upstream::core::check_upstream;
 use crate::{
-    build::logic as bl,
+    build::{
+        logic as bl,
+        transaction,
+    },
     cli::args::Args,
-    fetch::download::{
-        DownloadStatus,
-        download,
+    fetch::{
+        cache,
+        download::{
+            DownloadStatus,
+            download_all,
+        },
+    },
+    globals::{
+        config::CONFIG,
+        flags::Flags,
     },
-    globals::config::CONFIG,
     package::{
         Package,
+        dependencies::{
+            self,
+            ReverseIndex,
+            Stage,
+        },
+        files,
         history,
         parse::expand_set,
         stats,
+        verify,
     },
     remove::logic as rl,
-    shell::fs::mkdir,
+    shell::{
+        fs::{
+            mkdir,
+            rm,
+        },
+        user,
+    },
     utils::{
         comms::{
             erm,
@@ -37,8 +64,12 @@ use crate::{
             pr,
             vpr,
         },
-        fail::Fail,
+        fail::{
+            BoolFail,
+            Fail,
+        },
         hash::try_truncate_commit_hash,
+        json,
         time::Stopwatch,
     },
 };
@@ -61,11 +92,39 @@ impl PM<'_> {
     /// It interprets all PM-related cli flags and calls the necessary PM methods therefrom
     pub fn run(&self) {
         let a = self.args;
-        self.create_all_dotdirs_if_needed();
-        self.fetch_all_sources_if_needed(a);
+        if a.needs_root() {
+            transaction::recover().fail("Failed to recover from an interrupted install");
+        }
 
+        let steps = self.resolve_dependencies_if_needed(a);
+        if a.pretend {
+            return Plan::new(self.packages, &steps, a).display();
+        }
+        if !a.needs_root() && self.installs_dependencies(&steps) {
+            user::is_root().or_fail("2 requires root privileges to install missing dependencies");
+            transaction::recover().fail("Failed to recover from an interrupted install");
+        }
+        if a.needs_confirmation()
+            && !Flags::grab().yes
+            && !Plan::new(self.packages, &steps, a).confirm()
+        {
+            return msg!("󰜺  Aborted");
+        }
+
+        let packages = steps
+            .iter()
+            .filter(|(_, s)| *s == Stage::Full)
+            .map(|(p, _)| p.clone())
+            .collect::<Vec<_>>();
+        Self::create_all_dotdirs_if_needed(&packages, a);
+        self.fetch_all_sources_if_needed(&packages, a);
+
+        let mut index = Self::load_reverse_index_if_needed(a);
         if a.remove {
-            self.packages.iter().for_each(Self::remove);
+            self.remove_all(&mut index);
+        }
+        if a.autoremove {
+            Self::autoremove(&mut index);
         }
 
         #[cfg(feature = "upstream")]
@@ -75,17 +134,41 @@ impl PM<'_> {
         if a.get {
             self.get()
         }
+        if a.verify {
+            self.verify()
+        }
 
-        self.packages.iter().for_each(|p| {
-            if a.build {
-                Self::build(p)
-            }
-            if a.install {
-                Self::install(p)
+        let mut bootstrapped = HashSet::new();
+        for (p, stage) in &steps {
+            if *stage == Stage::Bootstrap {
+                if Self::bootstrap(p, self.is_explicit(p)) {
+                    bootstrapped.insert(&p.relpath);
+                }
+                continue;
             }
-            if a.update {
-                Self::update(p)
+
+            if bootstrapped.contains(&p.relpath) {
+                Self::rebuild(p);
+                if !self.is_explicit(p) {
+                    continue;
+                }
+            } else if self.is_explicit(p) {
+                if a.build {
+                    Self::build(p)
+                }
+                if a.install {
+                    Self::install(p, false)
+                }
+                if a.update {
+                    Self::update(p)
+                }
+            } else {
+                Self::install_dependency(p);
+                continue;
             }
+
+            mark_explicit_if_installed(p, a)
+                .efail(|| format!("Failed to mark '{p}' as explicitly installed"));
             if a.history {
                 Self::history(p)
             }
@@ -98,7 +181,13 @@ impl PM<'_> {
             if a.stats {
                 Self::stats(p)
             }
-        });
+            if a.rdeps {
+                Self::rdeps(p, &index)
+            }
+            if a.files {
+                files::list(p, a.only, a.tree)
+            }
+        }
 
         if a.prune {
             self.prune()
@@ -111,15 +200,128 @@ impl PM<'_> {
         }
     }
 
+    /// # Description
+    /// Returns true if a package was passed as an argument, rather than pulled in as a dependency
+    fn is_explicit(&self, p: &Package) -> bool {
+        self.packages.iter().any(|e| e.relpath == p.relpath)
+    }
+
+    /// # Description
+    /// Returns true if any resolved step installs a package that isn't installed yet
+    ///
+    /// Dependencies are installed even when only building, as are bootstrapped packages, so such
+    /// builds need root
+    fn installs_dependencies(&self, steps: &[(Package, Stage)]) -> bool {
+        steps.iter().any(|(p, stage)| {
+            !p.data.is_installed && (*stage == Stage::Bootstrap || !self.is_explicit(p))
+        })
+    }
+
+    /// # Description
+    /// Builds the reverse dependency index if certain cli flags are passed
+    ///
+    /// It's built once per run, since building it reads every installed package's LOCK
+    fn load_reverse_index_if_needed(args: &Args) -> ReverseIndex {
+        if args.remove || args.autoremove || args.rdeps {
+            ReverseIndex::load()
+        } else {
+            ReverseIndex::default()
+        }
+    }
+
+    /// # Description
+    /// Resolves dependencies for all packages in the PM struct if certain cli flags are passed
+    ///
+    /// Returns the packages and their dependencies in topological order, along with the stage each
+    /// should be built in. If dependencies aren't needed, returns the packages in the order they
+    /// were passed.
+    fn resolve_dependencies_if_needed(&self, args: &Args) -> Vec<(Package, Stage)> {
+        // 'if needed' means one of these are passed
+        if !(args.install || args.update || args.build) {
+            debug!("Dependencies were not resolved as they were not needed");
+            return self
+                .packages
+                .iter()
+                .map(|p| (p.clone(), Stage::Full))
+                .collect();
+        }
+
+        let resolved = dependencies::resolve(self.packages).fail("Failed to resolve dependencies");
+        debug!(
+            "Resolved order: {:?}",
+            resolved
+                .iter()
+                .map(|(p, s)| format!("{} ({s:?})", p.relpath))
+                .collect::<Vec<_>>()
+        );
+        resolved
+    }
+
+    /// # Description
+    /// Bootstraps a package to break a dependency cycle
+    ///
+    /// No bootstrap is needed if the package is already installed or its dist tarball exists. The
+    /// bootstrap dist tarball is removed after it's installed so it can't be mistaken for a full
+    /// build later.
+    ///
+    /// Packages passed as arguments are marked as explicitly installed, so they aren't orphaned
+    ///
+    /// Returns true if the package was bootstrapped and should be rebuilt
+    fn bootstrap(p: &Package, explicit: bool) -> bool {
+        if p.data.is_installed {
+            debug!("'{p}' is already installed, so it isn't bootstrapped");
+            return false;
+        }
+
+        let bootstrapped = !p.dist_exists();
+        if bootstrapped {
+            Self::build_stage(p, true, Stage::Bootstrap);
+            Self::install(p, true);
+            rm(&p.data.dist).efail(|| format!("Failed to remove bootstrap dist for '{p}'"));
+        } else {
+            msg!("󰏗  Installing '{p}'...");
+            Self::install(p, false);
+        }
+
+        let kind = if explicit { "explicitly installed" } else { "a dependency" };
+        p.mark_explicit(explicit)
+            .efail(|| format!("Failed to mark '{p}' as {kind}"));
+        bootstrapped
+    }
+
+    /// # Description
+    /// Rebuilds and reinstalls a bootstrapped package now that its dependencies exist
+    fn rebuild(p: &Package) {
+        msg!("󰏗  Rebuilding bootstrapped '{p}'...");
+        Self::build_stage(p, true, Stage::Full);
+        Self::install(p, true);
+    }
+
+    /// # Description
+    /// Installs a dependency if it isn't already installed
+    fn install_dependency(p: &Package) {
+        if p.data.is_installed {
+            debug!("Dependency '{p}' is already installed");
+            return;
+        }
+
+        msg!("󰏗  Installing dependency '{p}'...");
+        Self::install(p, false);
+        p.mark_explicit(false)
+            .efail(|| format!("Failed to mark '{p}' as a dependency"));
+    }
+
     /// # Description
     /// Installs all packages in the PM struct
-    fn install(p: &Package) {
+    ///
+    /// If ``r#override`` is true, reinstalls the package even if it's already installed
+    fn install(p: &Package, r#override: bool) {
         let mut stopwatch = Stopwatch::new();
         stopwatch.start();
 
         // Note: build and install both return stats because they check it anyway to see if
         // anything should be done
-        let status = bl::install(p);
+        let status = bl::install(p, r#override);
         stopwatch.stop();
         match status {
             | bl::InstallStatus::Already => {
@@ -130,7 +332,7 @@ impl PM<'_> {
             },
             | bl::InstallStatus::BuildFirst => {
                 PM::build(p);
-                PM::install(p);
+                PM::install(p, r#override);
             },
             | bl::InstallStatus::UpdateInstead => {
                 msg!("󰚰  Updating instead of installing '{p}'...");
@@ -166,11 +368,57 @@ impl PM<'_> {
 
     /// # Description
     /// Removes all packages in the PM struct
-    fn remove(p: &Package) {
+    ///
+    /// Dependents are removed before their dependencies so removing both together isn't refused
+    fn remove_all(&self, index: &mut ReverseIndex) {
+        let mut packages = dependencies::sort(self.packages).unwrap_or_else(|e| {
+            warn!("Failed to order removals: {e}");
+            self.packages.to_vec()
+        });
+        packages.reverse();
+        for p in &packages {
+            Self::remove(p, index);
+        }
+    }
+
+    /// # Description
+    /// Removes orphans until none remain
+    ///
+    /// Removing an orphan may orphan its dependencies, so orphans are found again after each pass.
+    /// Packages that couldn't be removed aren't attempted twice.
+    fn autoremove(index: &mut ReverseIndex) {
+        let installed = expand_set("//@i");
+        let mut attempted = HashSet::new();
+        loop {
+            let orphans = installed
+                .iter()
+                .filter(|p| !attempted.contains(&p.relpath) && index.is_orphan(p))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if orphans.is_empty() {
+                break;
+            }
+
+            for p in &orphans {
+                attempted.insert(p.relpath.clone());
+                Self::remove(p, index);
+            }
+        }
+
+        if attempted.is_empty() {
+            msg!("󰄹  No orphans to remove");
+        }
+    }
+
+    /// # Description
+    /// Removes a package, forgetting it in the reverse dependency index
+    fn remove(p: &Package, index: &mut ReverseIndex) {
         let mut stopwatch = Stopwatch::new();
         stopwatch.start();
 
-        if rl::remove(p) {
+        if rl::remove(p, index) {
+            index.forget(p);
             stopwatch.stop();
             msg!("󰄹  Removed '{p}' in {}", stopwatch.display());
         }
@@ -178,15 +426,25 @@ impl PM<'_> {
 
     /// # Description
     /// Builds all packages in the PM struct
-    fn build(p: &Package) {
+    fn build(p: &Package) { Self::build_stage(p, false, Stage::Full) }
+
+    /// # Description
+    /// Builds a package in a given stage
+    ///
+    /// If ``r#override`` is true, rebuilds the package even if it's already built
+    fn build_stage(p: &Package, r#override: bool, stage: Stage) {
         let mut stopwatch = Stopwatch::new();
         stopwatch.start();
 
-        let (status, package_stats) = bl::build(p, false);
+        let (status, package_stats) = bl::build(p, r#override, stage);
         stopwatch.stop();
         match status {
             | bl::BuildStatus::Source => {
-                msg!("󰄹  Built '{p}' in {}", stopwatch.display());
+                if stage == Stage::Bootstrap {
+                    msg!("󰄹  Bootstrapped '{p}' in {}", stopwatch.display());
+                } else {
+                    msg!("󰄹  Built '{p}' in {}", stopwatch.display());
+                }
 
                 let mut package_stats = package_stats
                     .efail(|| format!("[UNREACHABLE] Stats for '{p}' should be some but isn't?"));
@@ -204,15 +462,34 @@ impl PM<'_> {
     ///
     /// This is separate from ``fetch_all_sources_if_needed()``
     fn get(&self) {
-        self.packages.iter().for_each(|p| {
-            vpr!("Downloading sources for '{p}'...");
-
-            let status = download(p, self.args.force, &STY);
-            if matches!(status, DownloadStatus::Nothing) {
-                info!("Didn't download sources for '{p}'");
-            } else {
-                info!("Downloaded sources for '{p}'");
+        let packages = self.packages.iter().collect::<Vec<_>>();
+        vpr!("Downloading sources for {} packages...", packages.len());
+
+        let mut failures = Vec::new();
+        for (p, result) in download_all(&packages, self.args.force, &STY) {
+            match result {
+                | Ok(DownloadStatus::Nothing) => info!("Didn't download sources for '{p}'"),
+                | Ok(_) => info!("Downloaded sources for '{p}'"),
+                | Err(e) => failures.push((p, e)),
             }
+        }
+        Self::report_download_failures(&failures, packages.len());
+    }
+
+    /// # Description
+    /// Reports the packages whose sources failed to download
+    ///
+    /// **Fail conditions:**
+    /// - any package's sources failed to download
+    fn report_download_failures(failures: &[(&Package, anyhow::Error)], total: usize) {
+        for (p, e) in failures {
+            erm!("Failed to download sources for '{p}': {e:#}");
+        }
+        failures.is_empty().or_efail(|| {
+            format!(
+                "Failed to download sources for {} of {total} packages",
+                failures.len()
+            )
         });
     }
 
@@ -229,6 +506,7 @@ impl PM<'_> {
 
             total_count += count;
         });
+        total_count += cache::prune();
 
         stopwatch.stop();
         msg!(
@@ -274,7 +552,9 @@ impl PM<'_> {
     ///
     /// If none are provided, lists every package
     pub fn list_packages(packages: &[Package], msg: &str, imply: bool) {
-        msg!("{msg}:");
+        if !json::enabled() {
+            msg!("{msg}:");
+        }
 
         let mut pkgs = packages.to_vec();
         if pkgs.is_empty() {
@@ -293,6 +573,10 @@ impl PM<'_> {
             });
         }
 
+        if json::enabled() {
+            return pkgs.iter().for_each(|p| json::emit(&p.summary()));
+        }
+
         let max_width: usize = 48;
         for p in &pkgs {
             let package_info = format!(
@@ -337,11 +621,98 @@ impl PM<'_> {
             });
         }
         // TODO: Track time with stopwatch
-        msg!("Checked upstream versions for {len} packages");
+        if !json::enabled() {
+            msg!("Checked upstream versions for {len} packages");
+        }
+    }
+
+    /// # Description
+    /// Verifies installed files for all packages in the PM struct, or all installed packages if
+    /// none were passed
+    ///
+    /// Packages are verified in parallel, and reported in order
+    ///
+    /// **Fail conditions:**
+    /// - any package has modified or missing paths, or couldn't be verified
+    fn verify(&self) {
+        let pkgs = if self.packages.is_empty() { expand_set("//@i") } else { self.packages.into() };
+        let pkgs = pkgs
+            .into_iter()
+            .filter(|p| {
+                if !p.data.is_installed {
+                    erm!("Not installed: '{p}'");
+                }
+                p.data.is_installed
+            })
+            .collect::<Vec<_>>();
+
+        let mut stopwatch = Stopwatch::new();
+        stopwatch.start();
+
+        #[cfg(not(feature = "parallelism"))]
+        let results = pkgs.iter().map(verify::verify).collect::<Vec<_>>();
+
+        #[cfg(feature = "parallelism")]
+        let results = self
+            .thread_pool
+            .install(|| pkgs.par_iter().map(verify::verify).collect::<Vec<_>>());
+
+        stopwatch.stop();
+
+        let mut failed = 0;
+        for (p, result) in pkgs.iter().zip(results) {
+            let problems = match result {
+                | Ok(problems) => problems,
+                | Err(e) => {
+                    failed += 1;
+                    erm!("Failed to verify '{p}': {e:#}");
+                    continue;
+                },
+            };
+
+            if problems.is_empty() {
+                vpr!("Verified '{p}'");
+                continue;
+            }
+
+            failed += 1;
+            let missing = problems.iter().filter(|(_, p)| p.is_missing()).count();
+            let modified = problems.len() - missing;
+            erm!("'{p}': {modified} modified, {missing} missing");
+            for (path, problem) in &problems {
+                pr!("  {path}: {problem}");
+            }
+        }
+
+        msg!(
+            "Verified {} packages in {} ({failed} with problems)",
+            pkgs.len(),
+            stopwatch.display()
+        );
+        (failed == 0)
+            .or_efail(|| format!("{failed} of {} packages failed verification", pkgs.len()));
     }
 
     fn history(p: &Package) { history::view(p); }
 
+    /// # Description
+    /// Lists the installed packages that depend on a package
+    fn rdeps(p: &Package, index: &ReverseIndex) {
+        let rdeps = index.rdeps(p);
+        if rdeps.is_empty() {
+            if !json::enabled() {
+                msg!("No installed packages depend on '{p}'");
+            }
+            return;
+        }
+
+        Self::list_packages(
+            rdeps,
+            &format!("Installed packages depending on '{p}'"),
+            false,
+        );
+    }
+
     fn stats(p: &Package) {
         stats::load(p)
             .efail(|| format!("Failed to load package stats for '{p}'"))
@@ -351,37 +722,109 @@ impl PM<'_> {
     /// # Description
     /// Fetches the sources for all packages if certain cli flags are passed
     /// This logs "fetching" instead of "downloading" to differentiate between this and ``get()``
-    fn fetch_all_sources_if_needed(&self, args: &Args) {
+    fn fetch_all_sources_if_needed(&self, packages: &[Package], args: &Args) {
         // 'if needed' means one of these are passed
         if !(args.install || args.update || args.build) {
             debug!("Sources were not automatically fetched as they were not needed");
             return;
         }
 
-        self.packages.iter().for_each(|p| {
-            // don't bother downloading sources if the dist exists and the sources aren't needed
-            if p.dist_exists() && !args.build {
-                return;
-            }
-
-            if !matches!(download(p, false, &STY), DownloadStatus::Nothing) {
-                info!("Automatically fetched sources for '{p}'");
+        let packages = packages
+            .iter()
+            .filter(|p| {
+                let explicit = self.is_explicit(p);
+
+                // installed dependencies are left alone
+                if !explicit && p.data.is_installed {
+                    return false;
+                }
+
+                // don't bother downloading sources if the dist exists and the sources aren't needed
+                !p.dist_exists() || args.build && explicit
+            })
+            .collect::<Vec<_>>();
+
+        let mut failures = Vec::new();
+        for (p, result) in download_all(&packages, false, &STY) {
+            match result {
+                | Ok(DownloadStatus::Nothing) => {},
+                | Ok(_) => info!("Automatically fetched sources for '{p}'"),
+                | Err(e) => failures.push((p, e)),
             }
-        });
+        }
+        Self::report_download_failures(&failures, packages.len());
     }
 
     /// # Description
-    /// Creates necessary dotdirs for all packages contained in PM
-    fn create_all_dotdirs_if_needed(&self) {
+    /// Creates necessary dotdirs for all given packages
+    ///
+    /// Skipped for read-only operations, which may be run unprivileged
+    fn create_all_dotdirs_if_needed(packages: &[Package], args: &Args) {
         // 'if needed' means they don't exist
         const DOTDIRS: [&str; 5] = [".build", ".data", ".dist", ".logs", ".sources"];
 
-        self.packages.iter().for_each(|p| {
+        if args.is_read_only() {
+            debug!("Dotdirs were not created as the operation is read-only");
+            return;
+        }
+
+        for p in packages {
             for d in &DOTDIRS {
                 let dir = p.data.port_dir.join(d);
                 mkdir(&dir)
                     .efail(|| format!("Failed to create dotdir '{}' for '{p}'", dir.display()));
             }
-        });
+        }
+    }
+}
+
+/// # Description
+/// Marks a package the user named as explicitly installed, if it was installed
+///
+/// Only installing marks a package as explicit. Updating leaves the marker alone, so updating a
+/// dependency (by name, or with ``//@o``) doesn't stop it from becoming an orphan.
+fn mark_explicit_if_installed(p: &Package, args: &Args) -> anyhow::Result<()> {
+    if args.install && p.data.port_dir.join(".data/INSTALLED").exists() {
+        p.mark_explicit(true)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+
+    use super::mark_explicit_if_installed;
+    use crate::{
+        cli::args::Args,
+        utils::{
+            fail::Fail,
+            testing::{
+                self,
+                Scratch,
+            },
+        },
+    };
+
+    #[test]
+    fn updating_a_dependency_keeps_it_implicit() {
+        let port = Scratch::new("explicit");
+        fs::create_dir_all(port.join(".data")).fail("Failed to create port");
+        fs::write(port.join(".data/INSTALLED"), "1\n").fail("Failed to write");
+        fs::write(port.join(".data/EXPLICIT"), "false\n").fail("Failed to write");
+
+        let mut package = testing::package("zlib", "1", "");
+        package.data.port_dir = port.to_path_buf();
+        let explicit = || fs::read_to_string(port.join(".data/EXPLICIT")).fail("Failed to read");
+
+        let update = Args::parse_from(["2", "-u", "main/zlib"]);
+        mark_explicit_if_installed(&package, &update).fail("Failed to mark");
+        assert_eq!(explicit(), "false\n");
+
+        let install = Args::parse_from(["2", "-i", "main/zlib"]);
+        mark_explicit_if_installed(&package, &install).fail("Failed to mark");
+        assert_eq!(explicit(), "true\n");
     }
 }
@@ -2,6 +2,7 @@
 //! Defines the package manager struct and links to its endpoints
 
 pub mod endpoints;
+pub mod plan;
 
 #[cfg(feature = "parallelism")]
 pub use {
@@ -0,0 +1,487 @@
+// src/pm/plan.rs
+//! Plans actions without performing them, for ``--pretend``
+//!
+//! Plans follow the same decisions as ``PM::run()`` (see ``build::logic`` and ``remove::logic``),
+//! but nothing is fetched, built, installed, updated, or removed.
+
+use std::{
+    collections::HashSet,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+
+#[cfg(feature = "parallelism")]
+use rayon::prelude::*;
+use tracing::{
+    debug,
+    warn,
+};
+
+use crate::{
+    build::{
+        dist::{
+            self,
+            human,
+        },
+        logic as bl,
+    },
+    cli::args::Args,
+    fetch::download,
+    globals::flags::Flags,
+    package::{
+        Package,
+        dependencies::{
+            self,
+            ReverseIndex,
+            Stage,
+        },
+        parse::expand_set,
+        stats,
+    },
+    remove::logic as rl,
+    utils::{
+        comms::{
+            msg,
+            pr,
+            select,
+        },
+        time::Pretty,
+    },
+};
+
+/// # Description
+/// Something 2 would do
+pub enum Action {
+    /// Builds a package from source
+    Build(Package, Stage),
+    /// Installs a package from its dist tarball
+    Install {
+        package:    Package,
+        dependency: bool,
+    },
+    /// Updates a package, then removes its dead files
+    ///
+    /// Dead files are None if they can't be known until the package is built
+    Update {
+        package: Package,
+        dead:    Option<Vec<PathBuf>>,
+    },
+    /// Removes a package's paths
+    Remove {
+        package: Package,
+        paths:   Vec<PathBuf>,
+    },
+    /// Leaves a package alone
+    Skip {
+        package: Package,
+        reason:  String,
+    },
+}
+
+/// # Description
+/// A package's row in the transaction summary
+struct Row<'p> {
+    package:    &'p Package,
+    action:     &'static str,
+    /// Whether the package is built from source, rather than installed from its dist tarball
+    built:      bool,
+    /// The size of the sources that need downloading, if known
+    download:   Option<u64>,
+    /// The average of the package's previous build times, if any
+    build_time: Option<Duration>,
+}
+
+/// # Description
+/// The actions 2 would take, in order
+#[derive(Default)]
+pub struct Plan {
+    pub actions:  Vec<Action>,
+    /// Packages whose dist tarballs will exist by the time they're installed
+    built:        HashSet<String>,
+    /// Packages that will have been bootstrapped
+    bootstrapped: HashSet<String>,
+    /// Packages that will have been removed
+    removed:      HashSet<String>,
+    /// The reverse dependency index, for planning removals
+    index:        ReverseIndex,
+}
+
+impl Plan {
+    /// # Description
+    /// Plans the actions ``PM::run()`` would take for some packages
+    ///
+    /// ``steps`` are the packages and their dependencies, as ordered by
+    /// ``PM::resolve_dependencies_if_needed()``
+    pub fn new(packages: &[Package], steps: &[(Package, Stage)], args: &Args) -> Self {
+        let mut plan = Self::default();
+        if args.remove || args.autoremove {
+            plan.index = ReverseIndex::load();
+        }
+        let is_explicit = |p: &Package| packages.iter().any(|e| e.relpath == p.relpath);
+
+        if args.remove {
+            plan.remove_all(packages);
+        }
+        if args.autoremove {
+            plan.autoremove();
+        }
+
+        for (p, stage) in steps {
+            if *stage == Stage::Bootstrap {
+                plan.bootstrap(p);
+                continue;
+            }
+
+            if plan.bootstrapped.contains(&p.relpath) {
+                plan.build(p, Stage::Full, true);
+                plan.install(p, true, false);
+            } else if is_explicit(p) {
+                if args.build {
+                    plan.build(p, Stage::Full, false)
+                }
+                if args.install {
+                    plan.install(p, false, false)
+                }
+                if args.update {
+                    plan.update(p)
+                }
+            } else if !p.data.is_installed {
+                plan.install(p, false, true);
+            }
+        }
+
+        plan
+    }
+
+    /// # Description
+    /// Plans removing packages, dependents first
+    fn remove_all(&mut self, packages: &[Package]) {
+        let packages = dependencies::sort(packages).unwrap_or_else(|e| {
+            warn!("Failed to order removals: {e}");
+            packages.to_vec()
+        });
+        for p in packages.iter().rev() {
+            self.remove(p);
+        }
+    }
+
+    /// # Description
+    /// Plans removing orphans
+    ///
+    /// Only current orphans are planned, though removing them may orphan more packages
+    fn autoremove(&mut self) {
+        let orphans = expand_set("//@i")
+            .iter()
+            .filter(|p| self.index.is_orphan(p))
+            .cloned()
+            .collect::<Vec<_>>();
+        for p in &orphans {
+            self.remove(p);
+        }
+    }
+
+    /// # Description
+    /// Plans removing a package
+    fn remove(&mut self, p: &Package) {
+        match rl::removal(p, &self.removed, &self.index) {
+            | Ok(paths) => {
+                self.removed.insert(p.relpath.clone());
+                self.actions.push(Action::Remove {
+                    package: p.clone(),
+                    paths:   paths.into_iter().map(|(_, path)| path).collect(),
+                });
+            },
+            | Err(refusal) => self.skip(p, refusal.to_string()),
+        }
+    }
+
+    /// # Description
+    /// Plans bootstrapping a package to break a dependency cycle
+    ///
+    /// See ``PM::bootstrap()``
+    fn bootstrap(&mut self, p: &Package) {
+        if p.data.is_installed || p.dist_exists() {
+            if !p.data.is_installed {
+                self.install(p, false, true);
+            }
+            return;
+        }
+
+        self.build(p, Stage::Bootstrap, true);
+        self.install(p, true, true);
+        self.built.remove(&p.relpath);
+        self.bootstrapped.insert(p.relpath.clone());
+    }
+
+    /// # Description
+    /// Plans building a package
+    ///
+    /// See ``build::logic::build()``
+    fn build(&mut self, p: &Package, stage: Stage, r#override: bool) {
+        let planned = !r#override && self.built.contains(&p.relpath);
+        if bl::is_built(p, r#override) || planned {
+            return self.skip(p, "already built");
+        }
+
+        self.built.insert(p.relpath.clone());
+        self.actions.push(Action::Build(p.clone(), stage));
+    }
+
+    /// # Description
+    /// Plans installing a package
+    ///
+    /// See ``build::logic::install()``
+    fn install(&mut self, p: &Package, r#override: bool, dependency: bool) {
+        let install = Action::Install { package: p.clone(), dependency };
+
+        match bl::install_status(p, r#override) {
+            | bl::InstallStatus::Already => self.skip(p, "already installed"),
+            | bl::InstallStatus::Dist => self.actions.push(install),
+            | bl::InstallStatus::BuildFirst => {
+                if !self.built.contains(&p.relpath) {
+                    self.build(p, Stage::Full, false);
+                }
+                self.actions.push(install);
+            },
+            | bl::InstallStatus::UpdateInstead => self.update(p),
+        }
+    }
+
+    /// # Description
+    /// Plans updating a package
+    ///
+    /// See ``build::logic::update()``
+    fn update(&mut self, p: &Package) {
+        match bl::update_status(p) {
+            | bl::UpdateStatus::NotInstalled => self.skip(p, "not installed"),
+            | bl::UpdateStatus::Latest => self.skip(p, "up to date"),
+            | bl::UpdateStatus::BuildFirst => {
+                if !self.built.contains(&p.relpath) {
+                    self.build(p, Stage::Full, false);
+                }
+                self.actions.push(Action::Update {
+                    package: p.clone(),
+                    dead:    None,
+                });
+            },
+            | bl::UpdateStatus::Dist => self.actions.push(Action::Update {
+                package: p.clone(),
+                dead:    dead_files(p),
+            }),
+        }
+    }
+
+    fn skip(&mut self, p: &Package, reason: impl Into<String>) {
+        self.actions.push(Action::Skip {
+            package: p.clone(),
+            reason:  reason.into(),
+        });
+    }
+
+    /// # Description
+    /// Displays the plan
+    ///
+    /// Paths are listed unless quiet is passed
+    pub fn display(&self) {
+        msg!("󰈈  Pretending; nothing will be changed");
+        if self.actions.is_empty() {
+            return msg!("󰄹  Nothing to do");
+        }
+
+        let quiet = Flags::grab().quiet;
+        let list = |paths: &[PathBuf]| {
+            if quiet {
+                return;
+            }
+            for p in paths {
+                pr!("    '{}' -x", p.display());
+            }
+        };
+
+        for action in &self.actions {
+            match action {
+                | Action::Build(p, Stage::Bootstrap) => pr!("Would bootstrap '{p}'"),
+                | Action::Build(p, Stage::Full) => pr!("Would build '{p}'"),
+                | Action::Install { package, dependency: true } => {
+                    pr!("Would install dependency '{package}'")
+                },
+                | Action::Install { package, .. } => pr!("Would install '{package}'"),
+                | Action::Update { package, dead } => {
+                    pr!(
+                        "Would update '{}': '{}' -> '{}'",
+                        package.name,
+                        package.data.installed_version,
+                        package.version
+                    );
+                    match dead {
+                        | Some(dead) => list(dead),
+                        | None => pr!("    Dead files will be known once '{package}' is built"),
+                    }
+                },
+                | Action::Remove { package, paths } => {
+                    pr!("Would remove '{package}'");
+                    list(paths);
+                },
+                | Action::Skip { package, reason } => pr!("Would skip '{package}': {reason}"),
+            }
+        }
+    }
+
+    /// # Description
+    /// Groups the planned actions by package, leaving out skipped packages
+    ///
+    /// Builds are merged into the install or update they're for
+    fn rows(&self) -> Vec<Row<'_>> {
+        let mut rows: Vec<Row> = Vec::new();
+        for action in &self.actions {
+            let (package, action, built) = match action {
+                | Action::Build(p, Stage::Bootstrap) => (p, "bootstrap", true),
+                | Action::Build(p, Stage::Full) => (p, "build", true),
+                | Action::Install { package, .. } => (package, "install", false),
+                | Action::Update { package, .. } => (package, "update", false),
+                | Action::Remove { package, .. } => (package, "remove", false),
+                | Action::Skip { .. } => continue,
+            };
+
+            match rows.last_mut() {
+                | Some(last) if last.built && !built && last.package.relpath == package.relpath => {
+                    if last.action == "build" {
+                        last.action = action;
+                    }
+                },
+                | _ => rows.push(Row {
+                    package,
+                    action,
+                    built,
+                    download: None,
+                    build_time: None,
+                }),
+            }
+        }
+
+        let estimate = |row: &mut Row| {
+            if row.built {
+                row.download = download::missing(row.package)
+                    .iter()
+                    .map(|url| download::remote_size(url))
+                    .sum();
+                row.build_time = stats::load(row.package)
+                    .ok()
+                    .and_then(|s| s.average_build_time());
+            }
+        };
+
+        #[cfg(not(feature = "parallelism"))]
+        rows.iter_mut().for_each(estimate);
+
+        #[cfg(feature = "parallelism")]
+        rows.par_iter_mut().for_each(estimate);
+
+        rows
+    }
+
+    /// # Description
+    /// Displays a summary of the plan and asks whether to proceed
+    ///
+    /// Download sizes are only shown for packages built from source, and build times are estimated
+    /// from previous builds (see ``package::stats``). Returns true without asking if there's
+    /// nothing to do.
+    pub fn confirm(&self) -> bool {
+        let rows = self.rows();
+        if rows.is_empty() {
+            return true;
+        }
+
+        msg!("󰐊  Transaction summary:");
+        pr!(
+            "  {:<32} {:<24} {:<10} {:<6} {:>10} {:>12}",
+            "Package",
+            "Version",
+            "Action",
+            "From",
+            "Download",
+            "Build time"
+        );
+
+        let mut total_download = 0;
+        let mut total_build_time = Duration::ZERO;
+        let mut unknown = false;
+        for row in &rows {
+            let p = row.package;
+            let version = match row.action {
+                | "update" => format!("{} -> {}", p.data.installed_version, p.version),
+                | "remove" => p.data.installed_version.clone(),
+                | _ => p.version.clone(),
+            };
+
+            let (from, download, build_time) = if row.built {
+                total_download += row.download.unwrap_or_default();
+                total_build_time += row.build_time.unwrap_or_default();
+                unknown |= row.download.is_none() || row.build_time.is_none();
+                (
+                    "source",
+                    row.download.map_or_else(|| "?".to_string(), human),
+                    row.build_time
+                        .map_or_else(|| "?".to_string(), |d| format!("~{}", d.pretty())),
+                )
+            } else if row.action == "remove" {
+                ("", String::new(), String::new())
+            } else {
+                ("dist", String::new(), String::new())
+            };
+
+            pr!(
+                "  {:<32} {:<24} {:<10} {from:<6} {download:>10} {build_time:>12}",
+                p.relpath,
+                version,
+                row.action,
+            );
+        }
+
+        let plus = if unknown { "+" } else { "" };
+        msg!(
+            "󰐊  {} packages; {}{plus} to download; ~{}{plus} to build",
+            rows.len(),
+            human(total_download),
+            total_build_time.pretty()
+        );
+
+        let answer = select!("Proceed? [y/N]");
+        matches!(answer.to_lowercase().as_str(), "y" | "yes")
+    }
+}
+
+/// # Description
+/// Finds the dead files updating a package from its dist tarball would remove
+///
+/// Dead files are paths in the installed version's manifest that the new version doesn't install
+/// (see ``remove::logic::dead_files()``). Since the new version's manifest is only written once
+/// it's installed, the paths it would install are read from its dist tarball instead.
+///
+/// Returns None if the dead files couldn't be determined
+fn dead_files(p: &Package) -> Option<Vec<PathBuf>> {
+    if p.version == p.data.installed_version {
+        return Some(Vec::new());
+    }
+
+    let exclusions = dist::exclusions().ok()?;
+    let new = dist::list(&p.data.dist, &exclusions)
+        .inspect_err(|e| warn!("Failed to list dist for '{p}': {e}"))
+        .ok()?;
+    let new = new
+        .iter()
+        .map(|rel| Path::new("/").join(rel))
+        .collect::<HashSet<_>>();
+
+    let dead = rl::dead_files(p)?
+        .into_iter()
+        .filter(|(r, _)| !new.contains(Path::new(&r.path)))
+        .map(|(_, path)| path)
+        .collect::<Vec<_>>();
+
+    debug!("Found {} dead files for '{p}'", dead.len());
+    Some(dead)
+}
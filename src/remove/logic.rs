@@ -2,6 +2,12 @@
 //! Logic for package removal
 
 use std::{
+    collections::HashSet,
+    fmt::{
+        self,
+        Display,
+        Formatter,
+    },
     fs::{
         read_dir,
         remove_dir_all,
@@ -13,6 +19,7 @@ use std::{
 };
 
 use anyhow::Result;
+use glob::Pattern;
 use tracing::{
     debug,
     warn,
@@ -20,18 +27,27 @@ use tracing::{
 use walkdir::WalkDir;
 
 use super::manifest::{
+    Kind,
+    Record,
     find_dead_files,
     find_unique_paths,
 };
 use crate::{
+    db,
     globals::{
         config::CONFIG,
         flags::Flags,
     },
-    package::Package,
+    package::{
+        Package,
+        configs,
+        dependencies::ReverseIndex,
+    },
     shell::fs::{
         mkdir,
         rm,
+        rmdir,
+        rmf,
     },
     utils::{
         comms::{
@@ -76,70 +92,109 @@ const KEPT: [&str; 23] = [
     "/var",
 ];
 
+/// # Description
+/// Why a package can't be removed (see ``removal()``)
+#[derive(Debug)]
+pub enum Refusal {
+    /// The package isn't installed
+    NotInstalled,
+    /// The package is critical
+    Critical,
+    /// Installed packages depend on the package
+    Dependents(Vec<Package>),
+    /// The package's manifest couldn't be read
+    Manifest,
+}
+
+impl Display for Refusal {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            | Self::NotInstalled => write!(f, "not installed"),
+            | Self::Critical => write!(f, "critical package"),
+            | Self::Dependents(rdeps) => {
+                let rdeps = rdeps
+                    .iter()
+                    .map(|r| format!("{}/{r}", r.repo))
+                    .collect::<Vec<_>>();
+                write!(f, "installed packages depend on it: {}", rdeps.join(", "))
+            },
+            | Self::Manifest => write!(f, "missing manifest"),
+        }
+    }
+}
+
+impl Refusal {
+    /// # Description
+    /// Tells the user why a package wasn't removed
+    fn report(&self, package: &Package) {
+        match self {
+            | Self::NotInstalled => {
+                warn!("Not installed: '{package}'");
+                erm!("Not installed: '{package}'");
+            },
+            | Self::Critical => {
+                warn!("Refusing to remove critical package: '{package}'");
+                erm!("Refusing to remove critical package: '{package}'");
+            },
+            | Self::Dependents(rdeps) => {
+                warn!("Refusing to remove '{package}' as installed packages depend on it");
+                erm!("Refusing to remove '{package}' as installed packages depend on it:");
+                for r in rdeps {
+                    erm!("  {}/{r}", r.repo);
+                }
+            },
+            | Self::Manifest => warn!("Missing manifest for {package}"),
+        }
+    }
+}
+
 /// # Description
 /// Removes a package
 ///
 /// Removal entails reading a package's manifest and removing unique files. Some paths are
 /// protected against removal, even if they're unique.
 ///
-/// Returns false if the package isn't installed
+/// Returns false if the package isn't installed or installed packages depend on it (according to
+/// ``index``), unless force is passed
 /// Affected by quiet
 ///
 /// **Fail Conditions:**
 /// - the manifest doesn't exist
 /// - failed to remove a specific path (see ``rm()``)
-pub fn remove(package: &Package) -> bool {
-    let category = check_categories(package);
-    if !package.data.is_installed && !Flags::grab().force {
-        warn!("Not installed: '{package}'");
-        erm!("Not installed: '{package}'");
-        return false;
-    }
-
-    if category == Categories::Critical {
-        warn!("Refusing to remove critical package: '{package}'");
-        erm!("Refusing to remove critical package: '{package}'");
-        return false;
-    }
+pub fn remove(package: &Package, index: &ReverseIndex) -> bool {
+    let paths = match removal(package, &HashSet::new(), index) {
+        | Ok(paths) => paths,
+        | Err(refusal) => {
+            refusal.report(package);
+            return false;
+        },
+    };
 
-    if category == Categories::Core {
+    if check_categories(package) == Categories::Core {
         warn!("Removing core package: '{package}'");
         erm!("Removing core package: '{package}'");
     }
 
-    let manifest_name = format!("MANIFEST={}", package.version);
-    let manifest = package.data.port_dir.join(".data").join(manifest_name);
-
-    manifest.exists().or_fail("Manifest doesn't exist");
-
-    let Ok(unique) = find_unique_paths(&manifest) else {
-        warn!("Missing manifest for {package}");
-        return false;
-    };
-
     let quiet = Flags::grab().quiet;
-    unique.iter().for_each(|p| {
-        let pfx = Path::new(&CONFIG.general.prefix);
-        let p = p.trim_start_matches('/');
-        let path = pfx.join(p);
-
-        if KEPT.iter().any(|&s| path.ends_with(s)) {
-            debug!("Retaining protected path: '{}'", path.display());
-            return;
-        }
-
-        if let Err(e) = rm(&path) {
+    for (r, path) in &paths {
+        if let Err(e) = remove_path(path, r.kind) {
             warn!("Failed to remove path '{}': {e}", path.display());
         }
 
         if !quiet {
-            pr!("'{p}' -x");
+            pr!("'{}' -x", r.path.trim_start_matches('/'));
         }
-    });
+    }
 
     // NOTE: the manifest is not removed as prune handles that
     let status_file = package.data.port_dir.join(".data").join("INSTALLED");
     rm(&status_file).fail("Failed to remove the status file"); // this shouldn't happen
+    db::record_removal(&package.relpath).fail("Failed to update the database");
+
+    let explicit_marker = package.data.port_dir.join(".data").join("EXPLICIT");
+    if explicit_marker.exists() {
+        rm(&explicit_marker).fail("Failed to remove the explicit marker");
+    }
 
     if CONFIG.removal.remove_sources {
         remove_sources(package);
@@ -151,6 +206,116 @@ pub fn remove(package: &Package) -> bool {
     true
 }
 
+/// # Description
+/// Decides which paths removing a package would remove, without removing them
+///
+/// Returns why the package shouldn't be removed (see ``remove()``) without reporting it, so plans
+/// can show the reason. Packages in ``removed`` are treated as already removed, so they neither
+/// depend on the package nor share its paths. Dependents are looked up in ``index``.
+///
+/// **Fail Conditions:**
+/// - the manifest doesn't exist
+pub fn removal(
+    package: &Package,
+    removed: &HashSet<String>,
+    index: &ReverseIndex,
+) -> Result<Vec<(Record, PathBuf)>, Refusal> {
+    if !package.data.is_installed && !Flags::grab().force {
+        return Err(Refusal::NotInstalled);
+    }
+
+    if check_categories(package) == Categories::Critical {
+        return Err(Refusal::Critical);
+    }
+
+    let rdeps = index
+        .rdeps(package)
+        .iter()
+        .filter(|r| !removed.contains(&r.relpath))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !rdeps.is_empty() && !Flags::grab().force {
+        return Err(Refusal::Dependents(rdeps));
+    }
+
+    let manifest_name = format!("MANIFEST={}", package.version);
+    let manifest = package.data.port_dir.join(".data").join(manifest_name);
+
+    manifest.exists().or_fail("Manifest doesn't exist");
+
+    let Ok(unique) = find_unique_paths(package, &manifest, removed) else {
+        return Err(Refusal::Manifest);
+    };
+
+    Ok(removable(&unique))
+}
+
+/// # Description
+/// Pairs records with the paths they're installed to, leaving out paths that are never removed
+/// and modified configuration files
+fn removable(records: &[Record]) -> Vec<(Record, PathBuf)> {
+    let pfx = Path::new(&CONFIG.general.prefix);
+    let protected = configs::patterns().fail("Invalid protected paths");
+    let paths = records
+        .iter()
+        .map(|r| r.path.as_str())
+        .collect::<HashSet<_>>();
+
+    records
+        .iter()
+        .filter_map(|r| {
+            let p = r.path.trim_start_matches('/');
+            let path = pfx.join(p);
+
+            if KEPT.iter().any(|&s| path.ends_with(s)) {
+                debug!("Retaining protected path: '{}'", path.display());
+                return None;
+            }
+
+            let kept = paths.contains(&*configs::new_path(Path::new(&r.path)).to_string_lossy());
+            if keeps_modified_config(r, Path::new(p), &path, &protected, kept) {
+                return None;
+            }
+
+            Some((r.clone(), path))
+        })
+        .collect()
+}
+
+/// # Description
+/// Returns true if a path is a protected configuration file that was modified since it was
+/// installed, in which case it shouldn't be removed
+///
+/// Files that were ``kept`` over a new version, which was installed as ``.new``, are always treated
+/// as modified. See ``package::configs``.
+fn keeps_modified_config(
+    record: &Record,
+    rel: &Path,
+    path: &Path,
+    protected: &[Pattern],
+    kept: bool,
+) -> bool {
+    if !configs::is_protected(rel, protected) || !(kept || configs::is_modified(record, path)) {
+        return false;
+    }
+
+    debug!("Keeping modified configuration file: '{}'", path.display());
+    pr!("Keeping modified '{}'", path.display());
+    true
+}
+
+/// # Description
+/// Removes a path according to its type in the manifest
+///
+/// Paths of unknown type (from old manifests) are removed according to what they are on disk
+fn remove_path(path: &PathBuf, kind: Kind) -> Result<()> {
+    match kind {
+        | Kind::Dir => rmdir(path),
+        | Kind::File | Kind::Symlink | Kind::Other => rmf(path),
+        | Kind::Unknown => rm(path),
+    }
+}
+
 /// # Description
 /// Removes and recreates ``$PORT/.sources``
 fn remove_sources(package: &Package) {
@@ -179,36 +344,22 @@ fn remove_dist(package: &Package) {
 }
 
 /// # Description
-/// Removes dead files after an update
-pub fn remove_dead_files_after_update(package: &Package) {
+/// Decides which dead files would be removed after an update, without removing them
+///
+/// Dead files are paths in the installed version's manifest that no other manifest for the
+/// package contains. Returns None if the package isn't installed or its manifest is missing.
+pub fn dead_files(package: &Package) -> Option<Vec<(Record, PathBuf)>> {
     if !package.data.is_installed {
-        return erm!("'{package}' is not installed!");
+        erm!("'{package}' is not installed!");
+        return None;
     }
 
     let Ok(dead_files) = find_dead_files(package) else {
         warn!("Missing manifest for '{package}'");
-        return;
+        return None;
     };
 
-    let quiet = Flags::grab().quiet;
-    dead_files.iter().for_each(|p| {
-        let pfx = Path::new(&CONFIG.general.prefix);
-        let p = p.trim_start_matches('/');
-        let path = pfx.join(p);
-
-        if KEPT.iter().any(|&s| path.ends_with(s)) {
-            debug!("Retaining protected path: '{}'", path.display());
-            return;
-        }
-
-        if let Err(e) = rm(&path) {
-            warn!("Failed to remove path '{}': {e}", path.display())
-        }
-
-        if !quiet {
-            pr!("'{p}' -x");
-        }
-    });
+    Some(removable(&dead_files))
 }
 
 /// # Description
@@ -247,7 +398,8 @@ pub fn prune(package: &Package) -> usize {
         let entry = entry.fail("Invalid source entry");
         let path = entry.path();
 
-        if !path.is_file() {
+        // dangling symlinks are left behind when cached sources are pruned
+        if !path.is_file() && !path.is_symlink() {
             warn!(
                 "Detected non-file '{}' in '{}'",
                 path.display(),
@@ -1,23 +1,45 @@
 // src/remove/manifest.rs
-//! Reads the package manifest
+//! Reads and writes package manifests
+//!
+//! Manifests record every path a package installed, one per line, along with its type, mode,
+//! owner, size, hash, and symlink target:
+//!
+//! ```text
+//! #2 manifest v1
+//! d  0755  0:0  0  -  /usr/bin
+//! f  0755  0:0  86528  <hash>  /usr/bin/tree
+//! l  0777  0:0  0  -  /usr/bin/t  tree
+//! ```
+//!
+//! Fields are separated by tabs (shown as spaces above). Sizes and hashes (``twohash()``es) are
+//! only recorded for regular files. Manifests without the header are from before this format
+//! existed, and are read as bare path lists.
 
 use std::{
     collections::{
         HashMap,
         HashSet,
     },
+    fmt::{
+        self,
+        Display,
+        Formatter,
+    },
     fs,
+    io,
+    os::unix::fs::MetadataExt,
     path::{
         Path,
         PathBuf,
     },
     rc::Rc,
-    string::ToString,
+    str::FromStr,
 };
 
 use anyhow::{
     Context,
     Result,
+    bail,
 };
 use tracing::instrument;
 use walkdir::{
@@ -26,10 +48,228 @@ use walkdir::{
 };
 
 use crate::{
+    db::Db,
     package::Package,
-    utils::fail::Fail,
+    utils::{
+        fail::Fail,
+        hash::try_twohash,
+    },
 };
 
+/// # Description
+/// The first line of a structured manifest
+const HEADER: &str = "#2 manifest v1";
+
+/// # Description
+/// The type of a path in a manifest
+///
+/// Paths from old manifests are of unknown type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+    Unknown,
+}
+
+impl Kind {
+    pub const fn symbol(self) -> char {
+        match self {
+            | Self::File => 'f',
+            | Self::Dir => 'd',
+            | Self::Symlink => 'l',
+            | Self::Other => 'o',
+            | Self::Unknown => '?',
+        }
+    }
+
+    pub fn from_symbol(s: &str) -> Result<Self> {
+        Ok(match s {
+            | "f" => Self::File,
+            | "d" => Self::Dir,
+            | "l" => Self::Symlink,
+            | "o" => Self::Other,
+            | "?" => Self::Unknown,
+            | _ => bail!("Unknown path type '{s}'"),
+        })
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            | Self::File => "file",
+            | Self::Dir => "directory",
+            | Self::Symlink => "symlink",
+            | Self::Other => "special file",
+            | Self::Unknown => "unknown",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// # Description
+/// A path recorded in a manifest
+///
+/// ``path`` is relative to the prefix, but starts with a slash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub path:   String,
+    pub kind:   Kind,
+    pub mode:   u32,
+    pub uid:    u32,
+    pub gid:    u32,
+    pub size:   u64,
+    pub hash:   Option<String>,
+    pub target: Option<String>,
+}
+
+impl Record {
+    /// # Description
+    /// Creates a record for a path from an old manifest, about which nothing else is known
+    pub fn legacy(path: &str) -> Self {
+        Self {
+            path:   path.to_string(),
+            kind:   Kind::Unknown,
+            mode:   0,
+            uid:    0,
+            gid:    0,
+            size:   0,
+            hash:   None,
+            target: None,
+        }
+    }
+
+    /// # Description
+    /// Creates a record for ``path`` from the file at ``on_disk``
+    ///
+    /// Regular files are hashed
+    pub fn new(path: &str, on_disk: &Path) -> io::Result<Self> {
+        let meta = on_disk.symlink_metadata()?;
+        let ft = meta.file_type();
+
+        let kind = if ft.is_symlink() {
+            Kind::Symlink
+        } else if ft.is_dir() {
+            Kind::Dir
+        } else if ft.is_file() {
+            Kind::File
+        } else {
+            Kind::Other
+        };
+
+        let hash = if kind == Kind::File { Some(try_twohash(on_disk)?) } else { None };
+        let target = if kind == Kind::Symlink {
+            Some(fs::read_link(on_disk)?.to_string_lossy().to_string())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            kind,
+            mode: meta.mode() & 0o7777,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: if kind == Kind::File { meta.len() } else { 0 },
+            hash,
+            target,
+        })
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{:04o}\t{}:{}\t{}\t{}\t{}",
+            self.kind.symbol(),
+            self.mode,
+            self.uid,
+            self.gid,
+            self.size,
+            self.hash.as_deref().unwrap_or("-"),
+            self.path,
+        )?;
+
+        if let Some(target) = &self.target {
+            write!(f, "\t{target}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Record {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut fields = line.splitn(7, '\t');
+        let mut next = |name| {
+            fields
+                .next()
+                .with_context(|| format!("Missing {name} in '{line}'"))
+        };
+
+        let kind = Kind::from_symbol(next("type")?)?;
+        let mode = u32::from_str_radix(next("mode")?, 8).context("Invalid mode")?;
+        let (uid, gid) = next("owner")?.split_once(':').context("Invalid owner")?;
+        let size = next("size")?.parse().context("Invalid size")?;
+        let hash = Some(next("hash")?)
+            .filter(|h| *h != "-")
+            .map(ToString::to_string);
+        let path = next("path")?.to_string();
+        let target = fields.next().map(ToString::to_string);
+
+        Ok(Self {
+            path,
+            kind,
+            mode,
+            uid: uid.parse().context("Invalid uid")?,
+            gid: gid.parse().context("Invalid gid")?,
+            size,
+            hash,
+            target,
+        })
+    }
+}
+
+/// # Description
+/// Parses the contents of a manifest, in either format
+pub fn parse(contents: &str) -> Result<Vec<Record>> {
+    let mut lines = contents.lines().peekable();
+
+    if lines.peek() != Some(&HEADER) {
+        return Ok(lines
+            .filter(|l| !l.is_empty())
+            .map(Record::legacy)
+            .collect());
+    }
+
+    lines
+        .skip(1)
+        .filter(|l| !l.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// # Description
+/// Serializes records into a manifest
+pub fn serialize(records: &[Record]) -> String {
+    let mut lines = vec![HEADER.to_string()];
+    lines.extend(records.iter().map(ToString::to_string));
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// # Description
+/// Reads a manifest
+pub fn read(manifest: &Path) -> Result<Vec<Record>> {
+    let contents = fs::read_to_string(manifest)
+        .with_context(|| format!("Failed to read manifest '{}'", manifest.display()))?;
+    parse(&contents).with_context(|| format!("Invalid manifest '{}'", manifest.display()))
+}
+
 /// # Description
 /// Returns true if none of the directory entry's ancestors contain .data
 fn is_in_wrong_hidden(entry: &DirEntry) -> bool {
@@ -73,61 +313,76 @@ pub fn locate(dir: &str) -> Rc<[PathBuf]> {
 }
 
 /// # Description
-/// Reads manifests and returns a hashmap of their paths and their contents
+/// Reads manifests and returns a hashmap of their paths and their records
 #[instrument]
-fn read_all(manifests: &[PathBuf]) -> HashMap<PathBuf, Rc<[String]>> {
+fn read_all(manifests: &[PathBuf]) -> HashMap<PathBuf, Rc<[Record]>> {
     let mut data = HashMap::new();
 
     for manifest in manifests {
-        let contents = fs::read_to_string(manifest)
-            .efail(|| format!("Failed to open manifest '{}'", manifest.display()));
-        let lines: Rc<[String]> = contents.lines().map(ToString::to_string).collect();
-        data.insert(manifest.clone(), lines);
+        let records: Rc<[Record]> = read(manifest).fail("Failed to read manifest").into();
+        data.insert(manifest.clone(), records);
     }
 
     data
 }
 
 /// # Description
-/// Finds lines (which represent package install paths) unique to this manifest
+/// Finds records (which represent package install paths) unique to this manifest
 ///
-/// Backend for ``find_unique_paths``
+/// Backend for ``find_dead_files``
 ///
-/// Returns the unique lines in reverse order (meaning /path/to/file is above /path/to)
+/// Returns the unique records in reverse order (meaning /path/to/file is above /path/to)
 #[instrument]
 fn find_unique(
-    all_data: &HashMap<PathBuf, Rc<[String]>>,
+    all_data: &HashMap<PathBuf, Rc<[Record]>>,
     this_manifest: &PathBuf,
-) -> Result<Rc<[String]>> {
+) -> Result<Rc<[Record]>> {
     let this_data = all_data.get(this_manifest).context("Missing manifest")?;
-    let all_other_lines: HashSet<_> = all_data
+    let all_other_paths: HashSet<_> = all_data
         .iter()
         .filter(|(path, _)| *path != this_manifest)
-        .flat_map(|(_, lines)| lines.iter())
+        .flat_map(|(_, records)| records.iter().map(|r| r.path.as_str()))
         .collect();
 
     let unique = this_data
         .iter()
-        .filter(|l| !all_other_lines.contains(l))
+        .filter(|r| !all_other_paths.contains(r.path.as_str()))
         .cloned()
         .rev()
-        .collect::<Rc<[String]>>();
+        .collect::<Rc<[Record]>>();
 
     Ok(unique)
 }
 
 /// # Description
-/// Finds paths unique to a manifest
-pub fn find_unique_paths(manifest: &PathBuf) -> Result<Rc<[String]>> {
-    let manifests = locate("/var/ports");
-    let data = read_all(&manifests);
-    find_unique(&data, manifest)
+/// Finds paths in a package's manifest that no other installed package owns
+///
+/// Ownership is looked up in the database (see ``db``). Packages in ``removed`` are treated as
+/// already removed.
+///
+/// Returns the unique records in reverse order (meaning /path/to/file is above /path/to)
+pub fn find_unique_paths(
+    package: &Package,
+    manifest: &Path,
+    removed: &HashSet<String>,
+) -> Result<Rc<[Record]>> {
+    let db = Db::load()?;
+    let unique = read(manifest)?
+        .into_iter()
+        .filter(|r| {
+            db.owners(&r.path)
+                .iter()
+                .all(|o| *o == package.relpath || removed.contains(o))
+        })
+        .rev()
+        .collect();
+    Ok(unique)
 }
 
 /// # Description
 /// Finds unique files in an old manifest (dead files)
 #[instrument]
-pub fn find_dead_files(package: &Package) -> Result<Rc<[String]>> {
+pub fn find_dead_files(package: &Package) -> Result<Rc<[Record]>> {
     let manifests = locate(&format!(
         "/var/ports/{}/{}/.data",
         package.repo, package.name
@@ -142,3 +397,50 @@ pub fn find_dead_files(package: &Package) -> Result<Rc<[String]>> {
 
     find_unique(&data, &old_manifest)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Kind,
+        Record,
+        parse,
+        serialize,
+    };
+    use crate::utils::fail::Fail;
+
+    #[test]
+    fn old_manifests_are_read_as_paths() {
+        let records = parse("/usr\n/usr/bin\n/usr/bin/tree\n").fail("Failed to parse");
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.kind == Kind::Unknown));
+        assert_eq!(records[2].path, "/usr/bin/tree");
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = vec![
+            Record {
+                path:   "/usr/bin/tree".to_string(),
+                kind:   Kind::File,
+                mode:   0o755,
+                uid:    0,
+                gid:    0,
+                size:   86528,
+                hash:   Some("OXLcl0T2SZ8Pmy2_dmlvKuetivmyPd5m1q-Gyd-zaYY".to_string()),
+                target: None,
+            },
+            Record {
+                path:   "/usr/bin/t".to_string(),
+                kind:   Kind::Symlink,
+                mode:   0o777,
+                uid:    0,
+                gid:    0,
+                size:   0,
+                hash:   None,
+                target: Some("tree".to_string()),
+            },
+        ];
+
+        assert_eq!(parse(&serialize(&records)).ok(), Some(records));
+    }
+}
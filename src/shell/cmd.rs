@@ -9,6 +9,7 @@ use std::{
         BufWriter,
         Write,
     },
+    os::unix::process::CommandExt,
     path::PathBuf,
     process::{
         Command,
@@ -33,16 +34,26 @@ use crate::{
         config::CONFIG,
         flags::Flags,
     },
+    shell::user::User,
     utils::fail::Fail,
 };
 
 /// # Description
 /// Executes a command
 ///
+/// Equivalent to ``exec_as()`` without a user
+pub fn exec(command: &str, log: Option<PathBuf>) -> Result<()> { exec_as(command, log, None) }
+
+/// # Description
+/// Executes a command, optionally as another user
+///
 /// Sources /usr/share/2/envs/core
 ///
 /// Prints each line unless quiet is passed
 ///
+/// If a user is passed, the command runs as that user, with ``HOME``, ``USER``, and ``LOGNAME``
+/// set accordingly
+///
 /// **Fail conditions:**
 /// - command failed
 /// - bash wasn't found
@@ -50,10 +61,19 @@ use crate::{
 /// - some sync shenanigans (unlikely)
 /// - failing to read stderr/stdout (unlikely)
 #[instrument]
-pub fn exec(command: &str, log: Option<PathBuf>) -> Result<()> {
+pub fn exec_as(command: &str, log: Option<PathBuf>, user: Option<&User>) -> Result<()> {
     let quiet = Flags::grab().quiet;
 
-    let mut child = Command::new("bash")
+    let mut cmd = Command::new("bash");
+    if let Some(user) = user {
+        cmd.uid(user.uid)
+            .gid(user.gid)
+            .env("HOME", &user.home)
+            .env("USER", &user.name)
+            .env("LOGNAME", &user.name);
+    }
+
+    let mut child = cmd
         .arg("-c")
         .arg(command)
         .stdout(Stdio::piped())
@@ -151,12 +171,15 @@ pub fn exec(command: &str, log: Option<PathBuf>) -> Result<()> {
 ///
 /// This context is just sourcing ``$PORT/BUILD`` and setting environment
 /// variables.
+///
+/// Optionally takes a user to run the command as (see ``exec_as()``)
 #[macro_export]
 macro_rules! pkgexec {
-    ($cmd:expr, $pkg:expr) => {{
+    ($cmd:expr, $pkg:expr) => {{ $crate::shell::cmd::pkgexec!($cmd, $pkg, None) }};
+    ($cmd:expr, $pkg:expr, $user:expr) => {{
         use $crate::{
             globals::flags::Flags,
-            shell::cmd::exec,
+            shell::cmd::exec_as,
         };
 
         let debug = if Flags::grab().verbose { 'x' } else { ' ' };
@@ -178,7 +201,7 @@ macro_rules! pkgexec {
         );
 
         let build_log = $pkg.data.port_dir.join(".logs/build.log");
-        exec(&command, Some(build_log))
+        exec_as(&command, Some(build_log), $user)
     }};
 }
 
@@ -0,0 +1,134 @@
+// src/shell/user.rs
+//! Utilities for dropping privileges to the build user
+
+use std::{
+    ffi::{
+        CStr,
+        CString,
+    },
+    os::unix::fs::lchown,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::LazyLock,
+};
+
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
+use tracing::{
+    debug,
+    instrument,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    globals::config::CONFIG,
+    utils::fail::Fail,
+};
+
+/// # Description
+/// A user from the password database
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub uid:  u32,
+    pub gid:  u32,
+    pub home: PathBuf,
+}
+
+/// # Description
+/// The user builds are run as
+///
+/// This is None if 2 isn't running as root, or if ``build_user`` is empty in the config
+static BUILD_USER: LazyLock<Option<User>> = LazyLock::new(|| {
+    let name = &CONFIG.general.build_user;
+    if name.is_empty() || !is_root() {
+        return None;
+    }
+
+    let user = lookup(name).efail(|| format!("Failed to find build user '{name}'"));
+    debug!("Using build user: {user:?}");
+    Some(user)
+});
+
+/// # Description
+/// Returns true if 2 is running as root
+pub fn is_root() -> bool { unsafe { libc::geteuid() == 0 } }
+
+/// # Description
+/// Returns the user builds should drop to, if any
+pub fn build_user() -> Option<&'static User> { BUILD_USER.as_ref() }
+
+/// # Description
+/// Returns true if builds run without root privileges
+///
+/// This is the case if 2 drops to a build user, or if 2 isn't running as root to begin with
+pub fn builds_unprivileged() -> bool { build_user().is_some() || !is_root() }
+
+/// # Description
+/// Looks up a user by name in the password database
+///
+/// **Error conditions:**
+/// - the name contains a null byte
+/// - the user doesn't exist
+#[instrument]
+pub fn lookup(name: &str) -> Result<User> {
+    let cname = CString::new(name).context("Invalid user name")?;
+
+    // SAFETY: getpwnam returns either null or a pointer to a valid static passwd struct, which is
+    // copied out of immediately
+    let pw = unsafe { libc::getpwnam(cname.as_ptr()) };
+    if pw.is_null() {
+        bail!("No such user '{name}'");
+    }
+
+    Ok(unsafe { from_passwd(&*pw) })
+}
+
+/// # Description
+/// Looks up the user 2 is running as in the password database
+///
+/// **Error conditions:**
+/// - the user doesn't exist
+pub fn current() -> Result<User> {
+    // SAFETY: see ``lookup()``
+    let pw = unsafe { libc::getpwuid(libc::geteuid()) };
+    if pw.is_null() {
+        bail!("The current user doesn't exist");
+    }
+
+    Ok(unsafe { from_passwd(&*pw) })
+}
+
+/// # Description
+/// Copies a user out of a passwd struct
+///
+/// # Safety
+/// The struct's strings must be valid
+unsafe fn from_passwd(pw: &libc::passwd) -> User {
+    let string = |s| unsafe { CStr::from_ptr(s) }.to_string_lossy().to_string();
+
+    User {
+        name: string(pw.pw_name),
+        uid:  pw.pw_uid,
+        gid:  pw.pw_gid,
+        home: PathBuf::from(string(pw.pw_dir)),
+    }
+}
+
+/// # Description
+/// Recursively hands ownership of a path over to a user
+///
+/// Symlinks are not followed
+pub fn chown(path: &Path, user: &User) -> Result<()> {
+    for entry in WalkDir::new(path) {
+        let entry = entry?;
+        lchown(entry.path(), Some(user.uid), Some(user.gid))
+            .with_context(|| format!("Failed to chown '{}'", entry.path().display()))?;
+    }
+    Ok(())
+}
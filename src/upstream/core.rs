@@ -17,7 +17,10 @@ use anyhow::{
     Result,
     bail,
 };
-use serde::Deserialize;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 use tracing::{
     debug,
     instrument,
@@ -37,6 +40,7 @@ use crate::{
             is_commit_hash,
             try_truncate_commit_hash,
         },
+        json,
     },
 };
 
@@ -148,9 +152,41 @@ fn get_version(package: &Package) -> String {
     extract_version(stdout, package).to_string()
 }
 
+/// # Description
+/// A package's upstream check, as displayed by ``--json``
+///
+/// ``upstream_version`` is None if the check failed
+#[derive(Serialize, Debug)]
+struct UpstreamResult<'p> {
+    package:          &'p str,
+    version:          &'p str,
+    upstream_version: Option<&'p str>,
+    outdated:         bool,
+}
+
+impl<'p> UpstreamResult<'p> {
+    fn new(package: &'p Package, upstream_version: Option<&'p str>) -> Self {
+        Self {
+            package: &package.relpath,
+            version: &package.version,
+            upstream_version,
+            outdated: upstream_version.is_some_and(|v| {
+                try_truncate_commit_hash(v) != try_truncate_commit_hash(&package.version)
+            }),
+        }
+    }
+}
+
 /// # Description
 /// Handles displaying local vs upstream package versions for a package
 fn display_version(package: &Package, version: &str) {
+    if json::enabled() {
+        return json::emit(&UpstreamResult::new(
+            package,
+            Some(version).filter(|v| !v.is_empty()),
+        ));
+    }
+
     let max_pkg_len = 32;
 
     let pkg = format!("{}/{}", package.repo, package.name);
@@ -202,5 +238,9 @@ pub fn check_upstream(package: &Package) {
         }
         return display_version(package, &version);
     }
+
+    if json::enabled() {
+        json::emit(&UpstreamResult::new(package, None));
+    }
     erm!("Failed to check upstream version for '{package}'");
 }
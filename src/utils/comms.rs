@@ -80,7 +80,8 @@ macro_rules! pr {
 /// # Description
 /// Prints to stdout with the verbose formatting
 ///
-/// Unaffected by the quiet flag, enabled by the verbose flag
+/// Unaffected by the quiet flag, enabled by the verbose flag. Prints to stderr when JSON output
+/// was requested, so stdout stays machine-readable.
 #[macro_export]
 #[cfg(not(test))]
 macro_rules! vpr {
@@ -92,7 +93,12 @@ macro_rules! vpr {
                 .file_name()
                 .and_then(|n| n.to_str())
                 .unwrap_or("Unknown");
-            println!("{}[{}] {}\x1b[0m", CONFIG.message.verbose, f, format!($($arg)*))
+            let message = format!("{}[{}] {}\x1b[0m", CONFIG.message.verbose, f, format!($($arg)*));
+            if Flags::grab().json {
+                eprintln!("{message}")
+            } else {
+                println!("{message}")
+            }
         }
     }};
 }
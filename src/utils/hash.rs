@@ -4,7 +4,10 @@
 
 use std::{
     fs::File,
-    io::Read,
+    io::{
+        self,
+        Read,
+    },
     path::Path,
 };
 
@@ -20,19 +23,25 @@ use sha2::{
 use crate::utils::fail::Fail;
 
 pub fn twohash(file_path: &Path) -> String {
-    let mut file = File::open(file_path)
-        .efail(|| format!("Can't hash missing file: '{}'", file_path.display()));
+    try_twohash(file_path).efail(|| format!("Can't hash missing file: '{}'", file_path.display()))
+}
+
+/// # Description
+/// Hashes a file, returning an error instead of failing if it can't be read
+pub fn try_twohash(file_path: &Path) -> io::Result<String> {
+    let mut file = File::open(file_path)?;
     let mut hasher = Sha256::new();
     let mut buf = [0u8; 8192];
 
-    while let Ok(n) = file.read(&mut buf) {
+    loop {
+        let n = file.read(&mut buf)?;
         if n == 0 {
             break;
         }
         hasher.update(&buf[..n]);
     }
 
-    URL_SAFE_NO_PAD.encode(hasher.finalize())
+    Ok(URL_SAFE_NO_PAD.encode(hasher.finalize()))
 }
 
 pub fn is_commit_hash(s: &str) -> bool { s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit()) }
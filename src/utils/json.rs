@@ -0,0 +1,26 @@
+// src/utils/json.rs
+//! Utilities for machine-readable output
+//!
+//! When ``--json`` is passed, query endpoints print JSON Lines instead of formatted text: one JSON
+//! object per line, without ANSI escapes or glyphs. Errors and verbose messages are still sent to
+//! stderr.
+
+use serde::Serialize;
+
+use crate::{
+    globals::flags::Flags,
+    utils::fail::Fail,
+};
+
+/// # Description
+/// Returns true if JSON output was requested
+pub fn enabled() -> bool { Flags::grab().json }
+
+/// # Description
+/// Prints a value to stdout as a single line of JSON
+pub fn emit<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string(value).fail("Failed to serialize JSON")
+    );
+}
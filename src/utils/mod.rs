@@ -5,6 +5,9 @@ pub mod comms;
 pub mod esc;
 pub mod fail;
 pub mod hash;
+pub mod json;
+#[cfg(test)]
+pub mod testing;
 pub mod time;
 
 #[cfg(feature = "parallelism")]
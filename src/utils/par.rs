@@ -21,3 +21,16 @@ pub fn build_pool(packages: &[Package]) -> ThreadPool {
         .build()
         .fail("Failed to build thread pool")
 }
+
+/// # Description
+/// Builds a thread pool for downloading sources for some number of packages
+///
+/// The number of threads is capped by ``fetch.max_downloads``
+pub fn build_download_pool(count: usize) -> ThreadPool {
+    debug!("Building download thread pool...");
+    let num_threads = CONFIG.fetch.max_downloads.min(count).max(1);
+    ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .fail("Failed to build download thread pool")
+}
@@ -0,0 +1,65 @@
+// src/utils/testing.rs
+//! Fixtures shared by tests
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+};
+
+use crate::{
+    package::Package,
+    utils::fail::Fail,
+};
+
+/// # Description
+/// A scratch directory, removed when dropped
+///
+/// Each scratch directory is unique to the process and the fixture, so tests may run in parallel
+/// and leave nothing behind, even if they fail
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+    /// # Description
+    /// Creates an empty scratch directory under the temp directory
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("2-test-{name}-{}-{n}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).fail("Failed to create scratch directory");
+        Self(dir)
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+}
+
+/// # Description
+/// Creates a package in the main repo from a LOCK
+///
+/// ``extra`` is appended to the LOCK after its required fields
+pub fn package(name: &str, version: &str, extra: &str) -> Package {
+    let lock = format!(
+        "name = \"{name}\"\nrepo = \"main\"\nversion = \"{version}\"\ntimestamp = \"0\"\n{extra}"
+    );
+    let mut package: Package = toml::from_str(&lock).fail("Invalid LOCK");
+    package.relpath = format!("main/{name}");
+    package
+}
@@ -40,7 +40,9 @@ rm -rf \
     /usr/libexec/two \
     /usr/share/2 \
     /usr/bin/2 \
-    /etc/2
+    /etc/2 \
+    /var/lib/2 \
+    /var/cache/2
 echo "Uninstalled 2"
 
 confirm "Would you like to also remove '/var/ports'?" || {